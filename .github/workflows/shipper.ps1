@@ -12,6 +12,8 @@ rm smoothie-rs/bin/VapourSynth/
 
 $env:VAPOURSYNTH_LIB_DIR=(Get-Item ./smoothie-rs/bin/sdk/lib64/).FullName
 cargo build --release
+# not shipped, only makes sure the in-process renderer still builds against the SDK
+cargo build --features vapoursynth
 
 cp ./target/release/smoothie-rs.exe ./smoothie-rs/bin/
 if (-not(test-path ./smoothie-rs/bin/scripts/)){
@@ -1,3 +0,0 @@
-pub mod havsfunc;
-#[allow(unused)]
-pub mod output;
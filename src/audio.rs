@@ -0,0 +1,367 @@
+use crate::cuts;
+use crate::recipe::Recipe;
+use crate::verb;
+use crate::video::{self, Payload};
+use ffprobe::FfProbe;
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+/// An audio stream of the input file, `index` is relative to other audio streams (`1:a:{index}`)
+#[derive(Debug, Clone)]
+pub struct AudioTrack {
+    pub index: usize,
+    pub language: Option<String>,
+    pub title: Option<String>,
+    pub sample_rate: Option<u32>,
+}
+
+/// How `[audio] tempo:` follows the video when the timescale changes its speed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TempoMethod {
+    /// FFmpeg's atempo, keeps the pitch, always available
+    Atempo,
+    /// librubberband, keeps the pitch with less artifacts, needs an FFmpeg build with it
+    Rubberband,
+    /// changes the sample rate, pitch follows the speed like a tape would
+    Resample,
+}
+
+/// Reads an `[audio]` key, these are optional since most recipes don't have that section
+fn audio_opt(recipe: &Recipe, key: &str) -> String {
+    recipe
+        .get_option("audio", key)
+        .unwrap_or_default()
+        .trim()
+        .to_owned()
+}
+
+/// ffprobe-rs does not expose stream titles, ask ffprobe for them directly
+fn probe_track_titles(path: &Path) -> Vec<Option<String>> {
+    let output = Command::new("ffprobe")
+        .args([
+            "-v",
+            "error",
+            "-select_streams",
+            "a",
+            "-show_entries",
+            "stream_tags=title",
+            "-of",
+            "json",
+        ])
+        .arg(path)
+        .output()
+        .expect("Failed running ffprobe to get audio track titles");
+
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout)
+        .expect("Failed parsing ffprobe's audio track titles as JSON");
+
+    json["streams"]
+        .as_array()
+        .map(|streams| {
+            streams
+                .iter()
+                .map(|stream| stream["tags"]["title"].as_str().map(String::from))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+pub fn get_audio_tracks(payload: &Payload, with_titles: bool) -> Vec<AudioTrack> {
+    let titles = if with_titles {
+        probe_track_titles(&payload.in_path)
+    } else {
+        vec![]
+    };
+
+    payload
+        .probe
+        .streams
+        .iter()
+        .filter(|stream| stream.codec_type == Some("audio".to_owned()))
+        .enumerate()
+        .map(|(index, stream)| AudioTrack {
+            index,
+            language: stream.tags.as_ref().and_then(|tags| tags.language.clone()),
+            title: titles.get(index).cloned().flatten(),
+            sample_rate: stream
+                .sample_rate
+                .as_ref()
+                .and_then(|rate| rate.parse::<u32>().ok()),
+        })
+        .collect()
+}
+
+/// `[audio] tracks:` accepts `all`, `none`, or a comma separated list of
+/// track indexes (0, 2), languages (lang=eng) and titles (title=voice)
+pub fn select_tracks(tracks: &[AudioTrack], selection: &str) -> Vec<AudioTrack> {
+    let selection = selection.trim().to_lowercase();
+
+    if selection.is_empty() || selection == "all" {
+        return tracks.to_vec();
+    }
+    if crate::NO.contains(&selection.as_str()) {
+        return vec![];
+    }
+
+    let selectors: Vec<&str> = selection
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .collect();
+
+    tracks
+        .iter()
+        .filter(|track| {
+            selectors.iter().any(|selector| {
+                if let Some(lang) = selector.strip_prefix("lang=") {
+                    track
+                        .language
+                        .as_ref()
+                        .is_some_and(|l| l.to_lowercase() == lang.trim())
+                } else if let Some(title) = selector.strip_prefix("title=") {
+                    track
+                        .title
+                        .as_ref()
+                        .is_some_and(|t| t.to_lowercase().contains(title.trim()))
+                } else if let Ok(index) = selector.parse::<usize>() {
+                    track.index == index
+                } else {
+                    panic!("Unknown `[audio] tracks:` selector {selector:?}, expected an index, lang=xxx or title=xxx")
+                }
+            })
+        })
+        .cloned()
+        .collect()
+}
+
+/// atempo only takes 0.5 to 2.0 on older FFmpeg builds, chain it to reach any factor
+pub fn atempo_chain(mut speed: f64) -> String {
+    let mut chain: Vec<String> = vec![];
+
+    while speed > 2.0 {
+        chain.push("atempo=2.0".to_owned());
+        speed /= 2.0;
+    }
+    while speed < 0.5 {
+        chain.push("atempo=0.5".to_owned());
+        speed /= 0.5;
+    }
+    if (speed - 1.0).abs() > f64::EPSILON {
+        chain.push(format!("atempo={speed}"));
+    }
+
+    chain.join(",")
+}
+
+fn parse_scale(recipe: &Recipe, key: &str) -> f64 {
+    let scale = recipe
+        .get("timescale", key)
+        .trim()
+        .parse::<f64>()
+        .unwrap_or_else(|_| panic!("Failed parsing `[timescale] {key}:` to a number"));
+
+    if scale <= 0.0 {
+        panic!("`[timescale] {key}:` must be greater than 0, got {scale}");
+    }
+    scale
+}
+
+/// How much faster (or slower) the output plays compared to the source, see [video::FpsChain::speed]
+pub fn speed_factor(recipe: &Recipe, probe: &FfProbe) -> f64 {
+    let in_scale = parse_scale(recipe, "in");
+    let out_scale = parse_scale(recipe, "out");
+
+    match video::FpsChain::from_probe(recipe, probe) {
+        Some(chain) => {
+            let speed = chain.speed();
+            *speed.numer() as f64 / *speed.denom() as f64
+        }
+        // without the fps the truncation after `[timescale] out` can't be known
+        None => out_scale / in_scale,
+    }
+}
+
+fn tempo_method(recipe: &Recipe) -> TempoMethod {
+    match audio_opt(recipe, "tempo").to_lowercase().as_str() {
+        "" | "atempo" => TempoMethod::Atempo,
+        "rubberband" | "rubber band" => TempoMethod::Rubberband,
+        "resample" | "asetrate" | "pitch" => TempoMethod::Resample,
+        other => panic!(
+            "Unknown `[audio] tempo:` value {other:?}, expected atempo, rubberband or resample"
+        ),
+    }
+}
+
+/// `[audio] tempo range: 0.5-2` drops the audio if the speed factor lands outside of it
+fn tempo_range(recipe: &Recipe) -> Option<(f64, f64)> {
+    let range = audio_opt(recipe, "tempo range");
+    if crate::NO.contains(&range.as_str()) {
+        return None;
+    }
+
+    let (min, max) = range
+        .split_once('-')
+        .expect("Failed parsing `[audio] tempo range:`, expected e.g 0.5-2");
+
+    Some((
+        min.trim()
+            .parse::<f64>()
+            .expect("Failed parsing `[audio] tempo range:` minimum"),
+        max.trim()
+            .parse::<f64>()
+            .expect("Failed parsing `[audio] tempo range:` maximum"),
+    ))
+}
+
+/// Filter(s) to change an audio stream's speed by `speed`
+pub fn tempo_filter(method: TempoMethod, speed: f64, sample_rate: Option<u32>) -> String {
+    match method {
+        TempoMethod::Atempo => atempo_chain(speed),
+        TempoMethod::Rubberband => format!("rubberband=tempo={speed}"),
+        TempoMethod::Resample => {
+            let rate = sample_rate.unwrap_or(48000);
+            format!(
+                "asetrate={},aresample={rate}",
+                (rate as f64 * speed).round() as u32
+            )
+        }
+    }
+}
+
+/// Builds the audio side of the FFmpeg output (filter graph, maps and codec),
+/// expects the input video to be passed as the second input (`1:a`)
+pub fn build_audio_args(recipe: &Recipe, payload: &Payload) -> Vec<String> {
+    let selection = audio_opt(recipe, "tracks");
+    let tracks = get_audio_tracks(payload, selection.contains("title="));
+    let mut selected = select_tracks(&tracks, &selection);
+
+    let mut ret: Vec<String> = vec!["-map".to_owned(), "0:v".to_owned()];
+
+    let speed = speed_factor(recipe, &payload.probe);
+    let method = tempo_method(recipe);
+
+    if let Some((min, max)) = tempo_range(recipe) {
+        if speed < min || speed > max {
+            eprintln!(
+                "Audio speed factor {speed:.3} is outside of `[audio] tempo range:` ({min}-{max}), dropping audio"
+            );
+            selected.clear();
+        }
+    }
+
+    if selected.is_empty() {
+        verb!("No audio tracks selected, output will be silent");
+        return ret;
+    }
+
+    let timecodes = cuts::from_recipe(recipe);
+    let cut_type = cuts::CutType::from_recipe(recipe);
+    let source_duration = payload
+        .probe
+        .format
+        .duration
+        .as_ref()
+        .and_then(|duration| duration.parse::<f64>().ok())
+        .unwrap_or(0.0);
+
+    let downmix = audio_opt(recipe, "downmix").to_lowercase();
+    let downmix = match downmix.as_str() {
+        "stereo" | "mono" => Some(downmix),
+        _ if crate::NO.contains(&downmix.as_str()) => None,
+        _ => panic!("Unknown `[audio] downmix:` value {downmix:?}, expected stereo, mono or no"),
+    };
+
+    let normalize = audio_opt(recipe, "normalize");
+    let loudness: Option<f64> = if crate::NO.contains(&normalize.to_lowercase().as_str()) {
+        None
+    } else if crate::YES.contains(&normalize.to_lowercase().as_str()) {
+        Some(-16.0)
+    } else {
+        Some(normalize.parse::<f64>().expect(
+            "Failed parsing `[audio] normalize:`, expected yes/no or a LUFS target (e.g -14)",
+        ))
+    };
+
+    let merge = recipe.get_bool_or("audio", "merge tracks", false) && selected.len() > 1;
+
+    let mut filter_complex = String::new();
+    let mut outputs: Vec<String> = vec![];
+
+    for track in &selected {
+        let n = track.index;
+        let mut chain: Vec<String> = vec![];
+
+        if let Some(timecodes) = &timecodes {
+            filter_complex.push_str(&cuts::audio_filter(n, timecodes, cut_type, source_duration));
+        }
+
+        if (speed - 1.0).abs() > f64::EPSILON {
+            verb!("Audio track {n}: {method:?} tempo at {speed:.4}x");
+            chain.push(tempo_filter(method, speed, track.sample_rate));
+        }
+        if let Some(layout) = &downmix {
+            chain.push(format!("aformat=channel_layouts={layout}"));
+        }
+        if let Some(target) = loudness {
+            chain.push(format!("loudnorm=I={target}:TP=-1.5:LRA=11"));
+        }
+
+        let source = if timecodes.is_some() {
+            format!("[cut{n}]")
+        } else {
+            format!("[1:a:{n}]")
+        };
+
+        if chain.is_empty() {
+            if timecodes.is_some() || merge {
+                // pass it through the graph as-is so it can be mapped or mixed
+                filter_complex.push_str(&format!("{source}anull[outa{n}];"));
+                outputs.push(format!("[outa{n}]"));
+            } else {
+                outputs.push(format!("1:a:{n}"));
+            }
+        } else {
+            filter_complex.push_str(&format!("{source}{}[outa{n}];", chain.join(",")));
+            outputs.push(format!("[outa{n}]"));
+        }
+    }
+
+    if merge {
+        filter_complex.push_str(&format!(
+            "{}amix=inputs={}:duration=longest:normalize=0[outa];",
+            outputs.join(""),
+            outputs.len()
+        ));
+        outputs = vec!["[outa]".to_owned()];
+    }
+
+    let filtered = !filter_complex.is_empty();
+    if filtered {
+        verb!("Audio filter graph: {filter_complex}");
+        ret.push("-filter_complex".to_owned());
+        ret.push(filter_complex.trim_end_matches(';').to_owned());
+    }
+
+    for output in outputs {
+        ret.push("-map".to_owned());
+        ret.push(output);
+    }
+
+    let codec = audio_opt(recipe, "codec");
+    if !codec.is_empty() {
+        if codec == "copy" && filtered {
+            panic!("`[audio] codec: copy` can't be used while audio is being filtered (timecodes, timescale, downmix, normalize or merge tracks), set a codec like aac or opus");
+        }
+        ret.push("-c:a".to_owned());
+        ret.push(codec);
+    }
+
+    let bitrate = audio_opt(recipe, "bitrate");
+    if !bitrate.is_empty() {
+        ret.push("-b:a".to_owned());
+        ret.push(bitrate);
+    }
+
+    ret
+}
@@ -0,0 +1,365 @@
+use super::{
+    ffmpeg_options, find_ffmpeg, follow, output_args, video_input_args, RenderBackend, Report,
+};
+use crate::cancel::Watchdog;
+use crate::cli::Arguments;
+use crate::cmd::SmCommand;
+use crate::cuts::{self, CutType};
+use crate::recipe::Recipe;
+use crate::timecode;
+use crate::traceback::Failure;
+use crate::verb;
+use crate::video::FpsChain;
+use crate::weighting;
+use ffprobe::FfProbe;
+use num_rational::Ratio;
+use std::env;
+use std::io::Read;
+use std::process::{Child, Stdio};
+
+/// FFmpeg filtering the source on its own: `[frame blending]` with tmix, `[timescale]` and `[color grading]`
+/// (plus cuts and `[runtime] fpscap`), for machines without VapourSynth
+#[derive(Default)]
+pub struct Ffmpeg {
+    ffmpeg: Option<Child>,
+    blocks: Option<Box<dyn Read + Send>>,
+    errors: Vec<String>,
+}
+
+/// What's enabled in the recipe that only jamba.vpy can do, empty if FFmpeg can render it alone
+pub fn needs_vapoursynth(recipe: &Recipe) -> Vec<String> {
+    let mut needs: Vec<String> = [
+        "pre-interp",
+        "interpolation",
+        "flowblur",
+        "lut",
+        "artifact masking",
+    ]
+    .iter()
+    .filter(|section| recipe.get_bool_or(section, "enabled", false))
+    .map(|section| format!("[{section}]"))
+    .collect();
+
+    if let Some(threshold) = recipe.get_option("miscellaneous", "dedup threshold") {
+        if !crate::NO.contains(&threshold.trim().to_lowercase().as_str()) {
+            needs.push("[miscellaneous] dedup threshold".to_owned());
+        }
+    }
+
+    if recipe.get_bool_or("frame blending", "enabled", false) {
+        if recipe.get_bool_or("frame blending", "bright blend", false) {
+            needs.push("[frame blending] bright blend".to_owned());
+        }
+        let weighting = weighting::weighting_of(recipe);
+        if weighting::parse(&weighting, 240.0, 60.0, 1.0).is_err() {
+            needs.push(format!("[frame blending] weighting: {weighting}"));
+        }
+    }
+
+    // eq has no equivalent to Tweak's coring
+    if recipe.get_bool_or("color grading", "enabled", false)
+        && recipe.get_bool_or("color grading", "coring", false)
+    {
+        needs.push("[color grading] coring".to_owned());
+    }
+    needs
+}
+
+/// The `-vf` chain doing what jamba.vpy would, in the same order
+pub fn filters(recipe: &Recipe, probe: &FfProbe, peek: Option<u64>) -> Vec<String> {
+    let mut filters: Vec<String> = vec![];
+    let chain = FpsChain::from_probe(recipe, probe)
+        .expect("Failed getting the source's frame rate, FFmpeg can't blend without it");
+
+    if chain.capped != chain.source {
+        filters.push(format!("fps={}", chain.capped));
+    }
+
+    if let Some(timecodes) = cuts::from_recipe(recipe) {
+        let source_duration = probe
+            .format
+            .duration
+            .as_ref()
+            .and_then(|duration| duration.parse::<f64>().ok())
+            .unwrap_or(0.0);
+        filters.push(cuts::video_filter(
+            &timecodes,
+            CutType::from_recipe(recipe),
+            source_duration,
+        ));
+    }
+
+    // AssumeFPS in jamba.vpy: same frames, played at another rate (there's no interpolation here)
+    if chain.blending != chain.capped {
+        filters.push(format!("setpts=PTS*{}", chain.capped / chain.blending));
+        filters.push(format!("fps={}", chain.blending));
+    }
+
+    if recipe.get_bool_or("frame blending", "enabled", false) {
+        if recipe
+            .get_option("frame blending", "fps")
+            .and_then(|fps| timecode::parse_decimal(&fps).ok())
+            .is_none()
+        {
+            panic!("Failed parsing `[frame blending] fps:`");
+        }
+
+        // blending is skipped when it would not bring the fps down
+        if chain.output != chain.blending {
+            let (fps, blended) = (chain.blending, chain.output);
+            let weighting = weighting::weighting_of(recipe);
+            let intensity = recipe
+                .get("frame blending", "intensity")
+                .trim()
+                .parse::<f64>()
+                .expect("Failed parsing `[frame blending] intensity:`");
+
+            let weights = weighting::parse(
+                &weighting,
+                *fps.numer() as f64 / *fps.denom() as f64,
+                *blended.numer() as f64,
+                intensity,
+            )
+            .unwrap_or_else(|e| panic!("Invalid `[frame blending] weighting:` {weighting:?}: {e}"));
+
+            if weights.len() > 1 {
+                // tmix mixes a frame with the ones before it, jamba.vpy with the ones around it:
+                // the last frame is repeated like jamba.vpy pads, then the mix is shifted back by the radius
+                let radius = weights.len() / 2;
+                let weights: Vec<String> = weights.iter().map(|w| w.to_string()).collect();
+                filters.push(format!("tpad=stop_mode=clone:stop={radius}"));
+                filters.push(format!(
+                    "tmix=frames={}:weights='{}'",
+                    weights.len(),
+                    weights.join(" ")
+                ));
+                filters.push(format!("trim=start_frame={radius},setpts=PTS-STARTPTS"));
+            }
+
+            let step = fps / blended;
+            if step.is_integer() {
+                if step != Ratio::from_integer(1) {
+                    filters.push(format!("framestep={step}"));
+                }
+            } else {
+                filters.push(format!("fps={blended}"));
+            }
+        }
+    }
+
+    if recipe.get_bool_or("color grading", "enabled", false) {
+        let value = |key: &str| {
+            recipe
+                .get("color grading", key)
+                .trim()
+                .parse::<f64>()
+                .unwrap_or_else(|_| panic!("Failed parsing `[color grading] {key}:`"))
+        };
+
+        filters.push(format!("hue=h={}:s={}", value("hue"), value("saturation")));
+        // Tweak adds brightness to 8-bit luma values, eq's goes from -1 to 1
+        filters.push(format!(
+            "eq=contrast={}:brightness={}",
+            value("contrast"),
+            value("brightness") / 255.0
+        ));
+    }
+
+    if let Some(frame) = peek {
+        filters.push(format!("select='eq(n,{frame})'"));
+    }
+    filters
+}
+
+impl RenderBackend for Ffmpeg {
+    fn prepare(&self, cmd: &mut SmCommand, args: &Arguments, _index: usize) {
+        let needs = needs_vapoursynth(&cmd.recipe);
+        if !needs.is_empty() {
+            panic!(
+                "`[output] backend: ffmpeg` can't render {}, they need VapourSynth",
+                needs.join(", ")
+            );
+        }
+        if cmd.recipe.get_bool("preview window", "enabled") {
+            verb!("The preview window is only shown when rendering through VapourSynth");
+        }
+
+        cmd.process_path = find_ffmpeg(cmd);
+
+        // the source is read directly instead of vspipe's y4m
+        let mut ff_args = ffmpeg_options(&cmd.recipe);
+        match ff_args
+            .windows(2)
+            .position(|pair| pair[0] == "-i" && pair[1] == "-")
+        {
+            Some(i) => ff_args[i + 1] = cmd.payload.in_path.display().to_string(),
+            None => panic!("`[miscellaneous] ffmpeg options:` has to keep its `-i -` for FFmpeg to render on its own"),
+        }
+        if args.peek.is_none() {
+            video_input_args(cmd, &mut ff_args);
+        }
+
+        let filters = filters(&cmd.recipe, &cmd.payload.probe, args.peek);
+        let mut vf = if filters.is_empty() {
+            vec![]
+        } else {
+            vec!["-vf".to_owned(), filters.join(",")]
+        };
+        if args.peek.is_some() {
+            vf.append(&mut vec!["-frames:v".to_owned(), "1".to_owned()]);
+        }
+
+        let mut output = output_args(cmd, args);
+        // before `-y partial`, after every input
+        let at = output.len() - 2;
+        output.splice(at..at, vf);
+
+        ff_args.append(&mut output);
+        cmd.process_args = ff_args;
+    }
+
+    fn spawn(&mut self, cmd: &SmCommand, report: &Report) -> Result<(), Failure> {
+        verb!("FF args: {}", cmd.process_args.join(" "));
+
+        let (mut ffmpeg, channel) = super::spawn_ffmpeg(cmd, Stdio::null(), report, false);
+        self.blocks = channel.and_then(|channel| channel.reader(&mut ffmpeg));
+        self.ffmpeg = Some(ffmpeg);
+        Ok(())
+    }
+
+    fn progress(&mut self, cmd: &SmCommand, report: &Report) {
+        if let Some(ffmpeg) = self.ffmpeg.as_mut() {
+            self.errors = follow(cmd, ffmpeg, self.blocks.take(), report, None);
+        }
+    }
+
+    fn wait(&mut self) -> (Option<i32>, Option<Failure>) {
+        let status = self
+            .ffmpeg
+            .take()
+            .expect("FFmpeg was never spawned")
+            .wait()
+            .expect("Failed waiting for FFmpeg");
+
+        let failure = (!status.success()).then(|| Failure::Encoder {
+            status: status.code(),
+            errors: self.errors.clone(),
+        });
+        (status.code(), failure)
+    }
+
+    fn cancel(&self) -> Watchdog {
+        Watchdog::new(
+            self.ffmpeg
+                .iter()
+                .map(|ffmpeg| ("FFmpeg", ffmpeg.id()))
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recipe(values: &[(&str, &str, &str)]) -> Recipe {
+        let mut recipe = Recipe::new();
+        for (section, key, value) in values {
+            recipe.insert_value(section, key.to_string(), value.to_string());
+        }
+        recipe
+    }
+
+    fn probe(fps: &str, duration: &str) -> FfProbe {
+        let mut probe = FfProbe::default();
+        probe.streams.push(ffprobe::Stream {
+            codec_type: Some("video".to_owned()),
+            avg_frame_rate: fps.to_owned(),
+            ..Default::default()
+        });
+        probe.format.duration = Some(duration.to_owned());
+        probe
+    }
+
+    #[test]
+    fn blends_centered_then_grades() {
+        let rc = recipe(&[
+            ("frame blending", "enabled", "yes"),
+            ("frame blending", "fps", "60"),
+            ("frame blending", "intensity", "1.0"),
+            ("frame blending", "weighting", "equal"),
+            ("color grading", "enabled", "yes"),
+            ("color grading", "brightness", "25.5"),
+            ("color grading", "contrast", "1.2"),
+            ("color grading", "saturation", "1.5"),
+            ("color grading", "hue", "10"),
+        ]);
+
+        assert_eq!(
+            filters(&rc, &probe("240/1", "10.0"), None),
+            [
+                "tpad=stop_mode=clone:stop=2",
+                "tmix=frames=5:weights='0.2 0.2 0.2 0.2 0.2'",
+                "trim=start_frame=2,setpts=PTS-STARTPTS",
+                "framestep=4",
+                "hue=h=10:s=1.5",
+                "eq=contrast=1.2:brightness=0.1",
+            ]
+        );
+    }
+
+    #[test]
+    fn caps_cuts_and_retimes_before_blending() {
+        let rc = recipe(&[
+            ("runtime", "fpscap", "120"),
+            ("runtime", "timecodes", "0:01-0:02;0:05-EOF"),
+            ("runtime", "cut type", "trim"),
+            ("timescale", "in", "0.5"),
+            ("frame blending", "enabled", "yes"),
+            ("frame blending", "fps", "50"),
+            ("frame blending", "intensity", "1.0"),
+            ("frame blending", "weighting", "[1, 2, 1]"),
+        ]);
+        let (third, sixth) = ((1.0f64 / 3.0).to_string(), (1.0f64 / 6.0).to_string());
+
+        assert_eq!(
+            filters(&rc, &probe("240/1", "10.0"), None),
+            [
+                "fps=120".to_owned(),
+                "select='between(t,1,2)+between(t,5,10)',setpts=N/FRAME_RATE/TB".to_owned(),
+                "setpts=PTS*1/2".to_owned(),
+                "fps=240".to_owned(),
+                "tpad=stop_mode=clone:stop=2".to_owned(),
+                format!("tmix=frames=5:weights='{sixth} {sixth} {third} {sixth} {sixth}'"),
+                "trim=start_frame=2,setpts=PTS-STARTPTS".to_owned(),
+                // 240 isn't a multiple of 50
+                "fps=50".to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    fn plain_blending_needs_nothing() {
+        let rc = recipe(&[
+            ("frame blending", "enabled", "yes"),
+            ("frame blending", "weighting", "equal"),
+            ("color grading", "enabled", "yes"),
+            ("color grading", "coring", "no"),
+            ("artifact masking", "enabled", "no"),
+        ]);
+        assert!(needs_vapoursynth(&rc).is_empty());
+    }
+
+    #[test]
+    fn jamba_only_keys_are_listed() {
+        let rc = recipe(&[
+            ("artifact masking", "enabled", "yes"),
+            ("color grading", "enabled", "yes"),
+            ("color grading", "coring", "yes"),
+        ]);
+        assert_eq!(
+            needs_vapoursynth(&rc),
+            ["[artifact masking]", "[color grading] coring"]
+        );
+    }
+}
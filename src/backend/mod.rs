@@ -0,0 +1,514 @@
+//! What turns an [SmCommand] into a render, picked with `[output] backend:` (or --tompv / --tonull)
+
+pub mod ffmpeg;
+mod mpv;
+mod vspipe;
+
+use crate::audio::build_audio_args;
+use crate::cancel::{self, Watchdog};
+use crate::chapters::write_chapters;
+use crate::cli::Arguments;
+use crate::cmd::{self, SmCommand};
+use crate::cuts;
+use crate::ffpb;
+use crate::metadata::{self, build_metadata_args};
+use crate::parse::parse_encoding_args;
+use crate::progress::{self, Emitter};
+use crate::provenance;
+use crate::recipe::Recipe;
+use crate::traceback::{self, Failure};
+#[cfg(feature = "vapoursynth")]
+use crate::vapoursynth;
+use crate::verb;
+use crate::video::{self, partial_path};
+use std::env;
+use std::io::Read;
+use std::process::{Child, Command, Stdio};
+use std::thread::JoinHandle;
+use which::which;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    /// jamba.vpy through vspipe, encoded by FFmpeg (and shown by FFplay with the preview window on), the default
+    VSPipe,
+    /// jamba.vpy played back in mpv, nothing gets written
+    Mpv,
+    /// jamba.vpy through FFmpeg into nothing, for benchmarking / checking a recipe
+    Null,
+    /// FFmpeg's own filters, for when VapourSynth isn't installed and the recipe only needs simple blending
+    Ffmpeg,
+}
+
+impl Kind {
+    /// --tompv and --tonull win over `[output] backend:`
+    pub fn select(args: &Arguments, recipe: &Recipe) -> Kind {
+        if args.tompv {
+            return Kind::Mpv;
+        }
+        if args.tonull {
+            return Kind::Null;
+        }
+
+        match recipe
+            .get_option("output", "backend")
+            .unwrap_or_default()
+            .trim()
+            .to_lowercase()
+            .as_str()
+        {
+            "" | "auto" => {
+                if cfg!(feature = "vapoursynth") || cmd::find_vspipe(args).is_some() {
+                    return Kind::VSPipe;
+                }
+                let missing = ffmpeg::needs_vapoursynth(recipe);
+                if !missing.is_empty() {
+                    panic!(
+                        "vspipe binary in path/bin dir not found, and FFmpeg alone can't render {}",
+                        missing.join(", ")
+                    );
+                }
+                Kind::Ffmpeg
+            }
+            "vspipe" => Kind::VSPipe,
+            "mpv" => Kind::Mpv,
+            "null" => Kind::Null,
+            "ffmpeg" => Kind::Ffmpeg,
+            other => panic!(
+                "Unknown `[output] backend:` {other:?}, expected auto, vspipe, ffmpeg, mpv or null"
+            ),
+        }
+    }
+
+    /// Whether it writes to the output path, which then needs reserving
+    pub fn writes_output(self) -> bool {
+        matches!(self, Kind::VSPipe | Kind::Ffmpeg)
+    }
+
+    /// Whether it runs jamba.vpy, through vspipe or in-process
+    pub fn uses_vapoursynth(self) -> bool {
+        self != Kind::Ffmpeg
+    }
+
+    pub fn create(self) -> Box<dyn RenderBackend> {
+        match self {
+            Kind::VSPipe => Box::new(vspipe::VSPipe::new()),
+            Kind::Mpv => Box::new(mpv::Mpv::default()),
+            Kind::Null => Box::new(vspipe::VSPipe::null()),
+            Kind::Ffmpeg => Box::new(ffmpeg::Ffmpeg::default()),
+        }
+    }
+}
+
+/// How the render loop wants to hear about progress
+pub enum Report<'a> {
+    Off,
+    Bar,
+    Json { emitter: &'a Emitter, job: usize },
+}
+
+impl Report<'_> {
+    pub fn enabled(&self) -> bool {
+        !matches!(self, Report::Off)
+    }
+}
+
+/// One render at a time, render.rs takes care of everything around it (hooks, partial files, the queue)
+pub trait RenderBackend {
+    /// Called by build_commands for each video: finds what it runs and fills in `cmd`'s process / args,
+    /// panics if something's missing
+    fn prepare(&self, cmd: &mut SmCommand, args: &Arguments, index: usize);
+
+    /// Starts rendering, Err if it failed before getting anywhere
+    fn spawn(&mut self, cmd: &SmCommand, report: &Report) -> Result<(), Failure>;
+
+    /// Follows the render until its output ends
+    fn progress(&mut self, cmd: &SmCommand, report: &Report);
+
+    /// Waits for everything it started, along with the output process' exit code
+    fn wait(&mut self) -> (Option<i32>, Option<Failure>);
+
+    /// Stops what it started on a second Ctrl+C, finish it once the render was waited on
+    fn cancel(&self) -> Watchdog;
+}
+
+/// Output length in seconds as FFmpeg will report it, None if the source's duration is unknown
+pub fn expected_duration(cmd: &SmCommand) -> Option<f64> {
+    let source_duration = cmd
+        .payload
+        .probe
+        .format
+        .duration
+        .as_ref()?
+        .parse::<f64>()
+        .ok()?;
+
+    // cuts and timescale change the output's length, which is what FFmpeg reports
+    Some(cuts::output_duration(
+        &cmd.recipe,
+        source_duration,
+        &cmd.payload.probe,
+    ))
+}
+
+/// `[output] process:`, which has to be FFmpeg unless SM_ALLOW_MISC_OUTPUT=1
+fn find_ffmpeg(cmd: &SmCommand) -> String {
+    ffmpeg_path(&cmd.recipe).unwrap_or_else(|e| panic!("{e}"))
+}
+
+/// `[output] process:`, resolved through PATH when it's the default
+pub fn ffmpeg_path(recipe: &Recipe) -> Result<String, String> {
+    let ff_path = recipe.get("output", "process");
+    if ff_path == "ffmpeg" {
+        return which(ff_path)
+            .map(|path| path.display().to_string())
+            .map_err(|_| "FFmpeg has not been installed or has not been added to PATH".to_owned());
+    }
+
+    let is_ffmpeg: bool = ff_path.ends_with("ffmpeg") || ff_path.ends_with("ffmpeg.exe");
+    let r#override: bool = env::var("SM_ALLOW_MISC_OUTPUT") == Ok("1".to_owned());
+
+    if !is_ffmpeg && !r#override {
+        return Err("You specified an output process which does not have the filename 'ffmpeg', use `[output] backend:` for other players, or set the environment variable SM_ALLOW_MISC_OUTPUT to 1 to override this error message".to_owned());
+    }
+    Ok(ff_path)
+}
+
+/// `[miscellaneous] ffmpeg options:`, which take the first input (`-i -`) from stdin
+fn ffmpeg_options(recipe: &Recipe) -> Vec<String> {
+    recipe
+        .get("miscellaneous", "ffmpeg options")
+        .split(' ')
+        .map(String::from)
+        .collect()
+}
+
+/// Puts the options for the video input (rotation) in front of the first `-i`
+fn video_input_args(cmd: &SmCommand, ff_args: &mut Vec<String>) {
+    let at = ff_args
+        .iter()
+        .position(|arg| arg == "-i")
+        .expect("`[miscellaneous] ffmpeg options:` has to keep its `-i -`");
+    let input_args = metadata::input_args(&cmd.recipe, &cmd.payload, &cmd.process_path);
+    ff_args.splice(at..at, input_args);
+}
+
+/// Everything after FFmpeg's first input: the source again (input 1, audio), chapters (input 2),
+/// maps, metadata, encoding args and the partial output. A --peek only keeps the encoding args
+fn output_args(cmd: &mut SmCommand, args: &Arguments) -> Vec<String> {
+    let recipe = &cmd.recipe;
+    let payload = &cmd.payload;
+    let mut ff_args: Vec<String> = vec![];
+
+    let enc_args: Vec<String> = parse_encoding_args(args, recipe)
+        .split(' ')
+        .map(String::from)
+        .filter(|s| !s.is_empty())
+        .collect();
+
+    if args.peek.is_none() {
+        ff_args.append(&mut vec![
+            "-i".to_owned(),
+            payload.in_path.display().to_string(),
+        ]);
+        // input 2, has to come before any -map
+        cmd.chapters = write_chapters(recipe, payload);
+        if let Some(chapters) = &cmd.chapters {
+            ff_args.append(&mut vec!["-i".to_owned(), chapters.display().to_string()]);
+        }
+        if args.stripaudio {
+            ff_args.append(&mut vec!["-map".to_owned(), "0:v".to_owned()]);
+        } else {
+            ff_args.append(&mut build_audio_args(recipe, payload));
+        }
+        if cmd.chapters.is_some() {
+            ff_args.append(&mut vec!["-map_chapters".to_owned(), "2".to_owned()]);
+        }
+        ff_args.append(&mut build_metadata_args(recipe, payload, &cmd.process_path));
+
+        cmd.provenance = provenance::build(recipe, payload, &enc_args);
+        if let Some(provenance) = &cmd.provenance {
+            ff_args.append(&mut provenance::metadata_args(recipe, provenance));
+        }
+    }
+    ff_args.append(&mut enc_args.clone());
+    // the partial file was reserved by resolve_outpath, render.rs renames it once done
+    ff_args.push("-y".to_owned());
+    ff_args.push(partial_path(&cmd.payload.out_path).display().to_string());
+    ff_args
+}
+
+/// Spawns FFmpeg, along with the channel its progress comes through if it's being reported.
+/// Its stdout is piped when `previewing`, for FFplay to read
+fn spawn_ffmpeg(
+    cmd: &SmCommand,
+    stdin: Stdio,
+    report: &Report,
+    previewing: bool,
+) -> (Child, Option<progress::Channel>) {
+    // with the preview window on, FFmpeg's stdout goes to it and progress takes a socket instead
+    let channel = report.enabled().then(|| progress::Channel::new(previewing));
+
+    let mut ff_args = cmd.process_args.clone();
+    if let Some(channel) = &channel {
+        ff_args.splice(0..0, channel.ffmpeg_args());
+    }
+    // errors are told apart by their level, JSON consumers get warnings whatever the recipe's -loglevel
+    match report {
+        Report::Json { .. } => ffpb::print_levels(&mut ff_args, Some("warning")),
+        Report::Bar => ffpb::print_levels(&mut ff_args, None),
+        Report::Off => {}
+    }
+
+    let ffmpeg = cancel::detach(&mut Command::new(&cmd.process_path))
+        .args(ff_args)
+        .stdin(stdin)
+        .stdout(
+            if previewing
+                || channel
+                    .as_ref()
+                    .is_some_and(|channel| channel.uses_stdout())
+            {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            },
+        )
+        .stderr(if channel.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::inherit()
+        })
+        .spawn()
+        .expect("Failed in spawning FFmpeg child");
+
+    (ffmpeg, channel)
+}
+
+/// Reports FFmpeg's progress until it's done, returns the error lines it logged.
+/// `frames` is the exact amount of frames it'll output if known, it's estimated from the recipe otherwise
+fn follow(
+    cmd: &SmCommand,
+    ffmpeg: &mut Child,
+    blocks: Option<Box<dyn Read + Send>>,
+    report: &Report,
+    frames: Option<usize>,
+) -> Vec<String> {
+    // without a progress channel FFmpeg logs straight to the terminal, nothing to collect
+    let Some(blocks) = blocks else {
+        return vec![];
+    };
+    let duration = expected_duration(cmd);
+
+    match report {
+        Report::Json { emitter, job } => progress::watch(ffmpeg, blocks, emitter, *job, duration),
+        Report::Bar => {
+            let total_frames = frames.or_else(|| {
+                let fps = video::output_fps(&cmd.recipe, &cmd.payload.probe)?;
+                Some((duration? * *fps.numer() as f64 / *fps.denom() as f64).round() as usize)
+            });
+            ffpb::ffmpeg(ffmpeg, blocks, duration, total_frames).unwrap_or_else(|e| {
+                verb!("Progress bar stopped: {e}");
+                vec![]
+            })
+        }
+        Report::Off => vec![],
+    }
+}
+
+/// Runs FFmpeg with `cmd`'s output as input 0 next to what it was encoded from, labelled `[ref]` for `graph`:
+/// jamba.vpy's frames rendered again, or the source through the FFmpeg backend's filters.
+/// Returns FFmpeg's log, None for backends that don't write an output
+pub fn against_reference(cmd: &SmCommand, graph: &str) -> Option<Result<String, String>> {
+    let (input, chain, stdin, frames) = match cmd.backend {
+        Kind::VSPipe => match Frames::start(cmd) {
+            Ok((frames, pipe)) => ("-".to_owned(), "null".to_owned(), pipe, Some(frames)),
+            Err(failure) => return Some(Err(failure.to_string())),
+        },
+        Kind::Ffmpeg => {
+            let filters = ffmpeg::filters(&cmd.recipe, &cmd.payload.probe, None);
+            (
+                cmd.payload.in_path.display().to_string(),
+                if filters.is_empty() {
+                    "null".to_owned()
+                } else {
+                    filters.join(",")
+                },
+                Stdio::null(),
+                None,
+            )
+        }
+        Kind::Mpv | Kind::Null => return None,
+    };
+
+    let ff_args = vec![
+        "-hide_banner".to_owned(),
+        "-nostats".to_owned(),
+        "-i".to_owned(),
+        cmd.payload.out_path.display().to_string(),
+        "-i".to_owned(),
+        input,
+        "-lavfi".to_owned(),
+        format!("[1:v]{chain}[ref];{graph}"),
+        "-f".to_owned(),
+        "null".to_owned(),
+        "-".to_owned(),
+    ];
+    verb!("FF args: {}", ff_args.join(" "));
+
+    let mut child = cancel::detach(&mut Command::new(&cmd.process_path))
+        .args(&ff_args)
+        .stdin(stdin)
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed in spawning FFmpeg child");
+    let mut frames = frames;
+    if let Some(frames) = frames.as_mut() {
+        frames.attach(&mut child);
+    }
+
+    let mut processes = stop_order(&frames, &None, "FFmpeg", &None);
+    processes.push(("FFmpeg", child.id()));
+    let watchdog = Watchdog::new(processes);
+    let output = child.wait_with_output().expect("Failed waiting for FFmpeg");
+    watchdog.finish();
+
+    if let Some(frames) = frames {
+        let (ok, _status, log) = frames.finish();
+        if !ok {
+            return Some(Err(format!("VSPipe failed:\n{}", log.join("\n"))));
+        }
+    }
+    let log = String::from_utf8_lossy(&output.stderr).to_string();
+    if !output.status.success() {
+        return Some(Err(format!("FFmpeg failed:\n{}", log.trim())));
+    }
+    Some(Ok(log))
+}
+
+/// What renders jamba.vpy's frames and pipes them into the output process
+enum Frames {
+    #[cfg_attr(feature = "vapoursynth", allow(dead_code))]
+    VSPipe {
+        child: Child,
+        log: JoinHandle<Vec<String>>,
+    },
+    #[cfg(feature = "vapoursynth")]
+    InProcess(vapoursynth::InProcess),
+}
+
+impl Frames {
+    /// Starts rendering, along with what the output process' stdin should be
+    #[cfg(not(feature = "vapoursynth"))]
+    fn start(cmd: &SmCommand) -> Result<(Frames, Stdio), Failure> {
+        let mut child = cancel::detach(&mut Command::new(&cmd.vs_path))
+            .args(&cmd.vs_args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("Failed in spawning VSPipe child");
+
+        let pipe = child.stdout.take().expect("Failed piping out of VSPipe");
+        let log = traceback::capture(
+            child
+                .stderr
+                .take()
+                .expect("Failed capturing VSPipe's stderr"),
+        );
+        Ok((Frames::VSPipe { child, log }, Stdio::from(pipe)))
+    }
+
+    /// Starts rendering, along with what the output process' stdin should be
+    #[cfg(feature = "vapoursynth")]
+    fn start(cmd: &SmCommand) -> Result<(Frames, Stdio), Failure> {
+        verb!("Evaluating {} in-process", cmd.vs_args.join(" "));
+
+        match vapoursynth::InProcess::start(&cmd.vs_args) {
+            Ok(in_process) => Ok((Frames::InProcess(in_process), Stdio::piped())),
+            Err(e) => {
+                let log: Vec<String> = e.lines().map(String::from).collect();
+                Err(traceback::vapoursynth_failure(None, &log))
+            }
+        }
+    }
+
+    fn pid(&self) -> Option<u32> {
+        match self {
+            Frames::VSPipe { child, .. } => Some(child.id()),
+            #[cfg(feature = "vapoursynth")]
+            Frames::InProcess(_) => None,
+        }
+    }
+
+    /// Exact amount of frames that will come out, only known when rendering in-process
+    fn total(&self) -> Option<usize> {
+        match self {
+            Frames::VSPipe { .. } => None,
+            #[cfg(feature = "vapoursynth")]
+            Frames::InProcess(in_process) => Some(in_process.frames),
+        }
+    }
+
+    /// Hands the output process' stdin over once it's spawned
+    fn attach(&mut self, _output: &mut Child) {
+        #[cfg(feature = "vapoursynth")]
+        if let Frames::InProcess(in_process) = self {
+            in_process.feed(
+                _output
+                    .stdin
+                    .take()
+                    .expect("Failed piping into the output process"),
+            );
+        }
+    }
+
+    /// Whether it went fine, its exit code and what it logged
+    fn finish(self) -> (bool, Option<i32>, Vec<String>) {
+        match self {
+            Frames::VSPipe { mut child, log } => {
+                let status = child.wait().expect("Failed waiting for VSPipe");
+                (
+                    status.success(),
+                    status.code(),
+                    log.join().unwrap_or_default(),
+                )
+            }
+            #[cfg(feature = "vapoursynth")]
+            Frames::InProcess(in_process) => match in_process.finish() {
+                Ok(()) => (true, None, vec![]),
+                Err(e) => (false, None, e.lines().map(String::from).collect()),
+            },
+        }
+    }
+}
+
+/// vspipe's `--start` / `--end` for a single frame
+fn peek_args(frame: u64) -> Vec<String> {
+    vec![
+        "--start".to_owned(),
+        frame.to_string(),
+        "--end".to_owned(),
+        frame.to_string(),
+    ]
+}
+
+/// The processes a [Watchdog] stops, frames first so the output process sees the end of its input,
+/// then the preview window reading from it if there's one
+fn stop_order(
+    frames: &Option<Frames>,
+    output: &Option<Child>,
+    name: &'static str,
+    preview: &Option<Child>,
+) -> Vec<(&'static str, u32)> {
+    let mut processes = vec![];
+    if let Some(pid) = frames.as_ref().and_then(Frames::pid) {
+        processes.push(("VSPipe", pid));
+    }
+    if let Some(output) = output {
+        processes.push((name, output.id()));
+    }
+    if let Some(preview) = preview {
+        processes.push(("FFplay", preview.id()));
+    }
+    processes
+}
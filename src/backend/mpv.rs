@@ -0,0 +1,72 @@
+use super::{peek_args, stop_order, Frames, RenderBackend, Report};
+use crate::cancel::{self, Watchdog};
+use crate::cli::Arguments;
+use crate::cmd::SmCommand;
+use crate::traceback::{self, Failure};
+use crate::verb;
+use std::env;
+use std::process::{Child, Command};
+use which::which;
+
+/// vspipe piped into mpv, for looking at a recipe without rendering anything
+#[derive(Default)]
+pub struct Mpv {
+    frames: Option<Frames>,
+    mpv: Option<Child>,
+}
+
+impl RenderBackend for Mpv {
+    fn prepare(&self, cmd: &mut SmCommand, args: &Arguments, _index: usize) {
+        cmd.process_path = which("mpv")
+            .expect("mpv has not been installed or has not been added to PATH")
+            .display()
+            .to_string();
+        cmd.process_args = vec!["-".to_owned()];
+
+        if let Some(p) = args.peek {
+            cmd.vs_args.append(&mut peek_args(p));
+        }
+    }
+
+    fn spawn(&mut self, cmd: &SmCommand, _report: &Report) -> Result<(), Failure> {
+        verb!("mpv args: {}", cmd.process_args.join(" "));
+
+        let (mut frames, pipe) = Frames::start(cmd)?;
+        // mpv has its own OSD, there's no progress to report
+        let mut mpv = cancel::detach(&mut Command::new(&cmd.process_path))
+            .args(&cmd.process_args)
+            .stdin(pipe)
+            .spawn()
+            .expect("Failed in spawning mpv child");
+        frames.attach(&mut mpv);
+
+        self.frames = Some(frames);
+        self.mpv = Some(mpv);
+        Ok(())
+    }
+
+    fn progress(&mut self, _cmd: &SmCommand, _report: &Report) {}
+
+    fn wait(&mut self) -> (Option<i32>, Option<Failure>) {
+        let status = self
+            .mpv
+            .take()
+            .expect("mpv was never spawned")
+            .wait()
+            .expect("Failed waiting for mpv");
+        let (vs_ok, vs_status, vs_log) = self
+            .frames
+            .take()
+            .expect("VSPipe was never spawned")
+            .finish();
+
+        (
+            status.code(),
+            traceback::diagnose(vs_ok, vs_status, &vs_log, status, &[]),
+        )
+    }
+
+    fn cancel(&self) -> Watchdog {
+        Watchdog::new(stop_order(&self.frames, &self.mpv, "mpv", &None))
+    }
+}
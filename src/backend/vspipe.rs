@@ -0,0 +1,171 @@
+use super::{
+    ffmpeg_options, find_ffmpeg, follow, output_args, peek_args, spawn_ffmpeg, stop_order,
+    video_input_args, Frames, RenderBackend, Report,
+};
+use crate::cancel::{self, Watchdog};
+use crate::cli::Arguments;
+use crate::cmd::SmCommand;
+use crate::traceback::{self, Failure};
+use crate::verb;
+use std::env;
+use std::io::Read;
+use std::process::{Child, Command, Stdio};
+use which::which;
+
+/// vspipe piped into FFmpeg, which can pipe into FFplay for the preview window.
+/// Also renders into FFmpeg's null muxer for --tonull
+#[derive(Default)]
+pub struct VSPipe {
+    null: bool,
+    frames: Option<Frames>,
+    ffmpeg: Option<Child>,
+    ffplay: Option<Child>,
+    blocks: Option<Box<dyn Read + Send>>,
+    errors: Vec<String>,
+}
+
+impl VSPipe {
+    pub fn new() -> VSPipe {
+        VSPipe::default()
+    }
+
+    pub fn null() -> VSPipe {
+        VSPipe {
+            null: true,
+            ..VSPipe::default()
+        }
+    }
+}
+
+impl RenderBackend for VSPipe {
+    fn prepare(&self, cmd: &mut SmCommand, args: &Arguments, _index: usize) {
+        cmd.process_path = find_ffmpeg(cmd);
+        let mut ff_args = ffmpeg_options(&cmd.recipe);
+
+        if self.null {
+            ff_args.append(&mut vec![
+                "-i".to_owned(),
+                cmd.payload.in_path.display().to_string(),
+                "-f".to_owned(),
+                "null".to_owned(),
+                "NUL".to_owned(),
+            ]);
+            cmd.process_args = ff_args;
+            return;
+        }
+
+        if let Some(p) = args.peek {
+            cmd.vs_args.append(&mut peek_args(p));
+        }
+        if args.peek.is_none() {
+            video_input_args(cmd, &mut ff_args);
+        }
+        ff_args.append(&mut output_args(cmd, args));
+
+        let recipe = &cmd.recipe;
+        if recipe.get_bool("preview window", "enabled") && args.peek.is_none() {
+            ff_args.append(
+                &mut recipe
+                    .get("preview window", "output args")
+                    .split(' ')
+                    .map(String::from)
+                    .collect(),
+            );
+
+            let mut ffplay_path = recipe.get("preview window", "process");
+            if ffplay_path == "ffplay" {
+                ffplay_path = which(ffplay_path)
+                    .expect(
+                        "FFplay (previewer) has not been installed or has not been added to PATH",
+                    )
+                    .display()
+                    .to_string()
+            };
+            cmd.ffplay_args = Some(
+                recipe
+                    .get("miscellaneous", "ffplay options")
+                    .split(' ')
+                    .map(String::from)
+                    .collect(),
+            );
+            cmd.ffplay_path = Some(ffplay_path);
+        }
+        cmd.process_args = ff_args;
+    }
+
+    fn spawn(&mut self, cmd: &SmCommand, report: &Report) -> Result<(), Failure> {
+        let previewing: bool = cmd.ffplay_path.is_some();
+
+        verb!("FF args: {}", cmd.process_args.join(" "));
+        if previewing {
+            verb!(
+                "FFplay args: {}",
+                cmd.ffplay_args.as_ref().unwrap().join(" ")
+            );
+        }
+
+        let (mut frames, pipe) = Frames::start(cmd)?;
+        let (mut ffmpeg, channel) = spawn_ffmpeg(cmd, pipe, report, previewing);
+        frames.attach(&mut ffmpeg);
+
+        if previewing {
+            let ffplay_pipe = ffmpeg.stdout.take().expect("Failed piping out of FFmpeg");
+            self.ffplay = Some(
+                cancel::detach(&mut Command::new(cmd.ffplay_path.as_ref().unwrap()))
+                    .args(cmd.ffplay_args.as_ref().unwrap())
+                    .stdin(ffplay_pipe)
+                    // its own stats would draw over the progress bar
+                    .stderr(if channel.is_some() {
+                        Stdio::null()
+                    } else {
+                        Stdio::inherit()
+                    })
+                    .spawn()
+                    .expect("Failed in spawning ffplay child"),
+            );
+        }
+
+        self.blocks = channel.and_then(|channel| channel.reader(&mut ffmpeg));
+        self.frames = Some(frames);
+        self.ffmpeg = Some(ffmpeg);
+        Ok(())
+    }
+
+    fn progress(&mut self, cmd: &SmCommand, report: &Report) {
+        let frames = self.frames.as_ref().and_then(Frames::total);
+        if let Some(ffmpeg) = self.ffmpeg.as_mut() {
+            self.errors = follow(cmd, ffmpeg, self.blocks.take(), report, frames);
+        }
+    }
+
+    fn wait(&mut self) -> (Option<i32>, Option<Failure>) {
+        let status = self
+            .ffmpeg
+            .take()
+            .expect("FFmpeg was never spawned")
+            .wait()
+            .expect("Failed waiting for FFmpeg");
+        let (vs_ok, vs_status, vs_log) = self
+            .frames
+            .take()
+            .expect("VSPipe was never spawned")
+            .finish();
+        if let Some(ffplay) = self.ffplay.take() {
+            ffplay.wait_with_output().unwrap();
+        }
+
+        (
+            status.code(),
+            traceback::diagnose(vs_ok, vs_status, &vs_log, status, &self.errors),
+        )
+    }
+
+    fn cancel(&self) -> Watchdog {
+        Watchdog::new(stop_order(
+            &self.frames,
+            &self.ffmpeg,
+            "FFmpeg",
+            &self.ffplay,
+        ))
+    }
+}
@@ -0,0 +1,196 @@
+use crate::verb;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Once};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How long each process gets to exit on its own before the next one is stopped
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+static PRESSES: AtomicUsize = AtomicUsize::new(0);
+static INSTALL: Once = Once::new();
+/// What the running [Watchdog]s look after, killed if smoothie has to exit right away
+static CHILDREN: Mutex<Vec<u32>> = Mutex::new(vec![]);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stop {
+    Continue,
+    /// First Ctrl+C, the current render finishes then the queue stops
+    AfterJob,
+    /// Second Ctrl+C, the current render is stopped too
+    Abort,
+}
+
+/// Catches Ctrl+C (and SIGTERM / SIGHUP), once for the whole queue
+pub fn install() {
+    INSTALL.call_once(|| {
+        let set = ctrlc::set_handler(|| match PRESSES.fetch_add(1, Ordering::SeqCst) {
+            0 => eprintln!(
+                "\nFinishing the current render then stopping, press Ctrl+C again to abort it"
+            ),
+            1 => eprintln!("\nAborting the current render.."),
+            _ => {
+                // they're detached, nothing else would take them down with smoothie
+                for pid in CHILDREN.lock().map(|pids| pids.clone()).unwrap_or_default() {
+                    kill_group(pid);
+                }
+                std::process::exit(130)
+            }
+        });
+        if let Err(e) = set {
+            verb!("Failed catching Ctrl+C, cancelling won't clean up: {e}");
+        }
+    });
+}
+
+pub fn requested() -> Stop {
+    match PRESSES.load(Ordering::SeqCst) {
+        0 => Stop::Continue,
+        1 => Stop::AfterJob,
+        _ => Stop::Abort,
+    }
+}
+
+/// Puts a child in its own process group, so the terminal's Ctrl+C only reaches smoothie, which decides what to do with it
+pub fn detach(command: &mut Command) -> &mut Command {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        const CREATE_NEW_PROCESS_GROUP: u32 = 0x00000200;
+        command.creation_flags(CREATE_NEW_PROCESS_GROUP);
+    }
+    command
+}
+
+#[cfg(unix)]
+fn signal(pid: u32, force: bool) {
+    let signal = if force { libc::SIGKILL } else { libc::SIGTERM };
+    unsafe {
+        libc::kill(pid as libc::pid_t, signal);
+    }
+}
+
+#[cfg(windows)]
+fn signal(pid: u32, _force: bool) {
+    // console apps without a window can't be asked nicely
+    let _ = Command::new("taskkill")
+        .args(["/PID", &pid.to_string(), "/T", "/F"])
+        .output();
+}
+
+/// Kills a detached child along with everything it started, which share its process group
+pub fn kill_group(pid: u32) {
+    #[cfg(unix)]
+    unsafe {
+        libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
+    }
+    #[cfg(windows)]
+    signal(pid, true);
+}
+
+/// Waits for a second Ctrl+C while a job renders, then stops its processes in the order given,
+/// e.g. vspipe first so FFmpeg sees the end of its input and wraps up, FFmpeg if it doesn't within the timeout.
+/// Whatever's still running after that gets killed
+pub struct Watchdog {
+    finished: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    pids: Vec<u32>,
+}
+
+impl Watchdog {
+    /// `processes` are names (for logging) and pids, in the order they should be stopped
+    pub fn new(processes: Vec<(&'static str, u32)>) -> Watchdog {
+        let finished = Arc::new(AtomicBool::new(false));
+        let pids: Vec<u32> = processes.iter().map(|(_, pid)| *pid).collect();
+        if let Ok(mut children) = CHILDREN.lock() {
+            children.extend(&pids);
+        }
+
+        let thread = {
+            let finished = finished.clone();
+            thread::spawn(move || {
+                let exited = |timeout: Duration| {
+                    let start = Instant::now();
+                    while start.elapsed() < timeout {
+                        if finished.load(Ordering::SeqCst) {
+                            return true;
+                        }
+                        thread::sleep(Duration::from_millis(50));
+                    }
+                    false
+                };
+
+                while requested() != Stop::Abort {
+                    if exited(Duration::from_millis(100)) {
+                        return;
+                    }
+                }
+
+                for (name, pid) in &processes {
+                    verb!("Stopping {name} ({pid})");
+                    signal(*pid, false);
+                    if exited(TIMEOUT) {
+                        return;
+                    }
+                }
+                for (name, pid) in &processes {
+                    verb!("Killing {name} ({pid})");
+                    signal(*pid, true);
+                }
+            })
+        };
+
+        Watchdog {
+            finished,
+            thread: Some(thread),
+            pids,
+        }
+    }
+
+    /// Call once the render was waited on
+    pub fn finish(mut self) {
+        self.finished.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        if let Ok(mut children) = CHILDREN.lock() {
+            children.retain(|pid| !self.pids.contains(pid));
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Pending,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+/// A render in the queue, as written to the state file when it gets cancelled
+#[derive(Debug, Clone, Serialize)]
+pub struct Job {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub state: JobState,
+}
+
+/// Writes `smoothie-state.json` next to `output` so whatever was left can be picked back up
+pub fn write_state(jobs: &[Job], output: &Path) -> PathBuf {
+    let path = output.with_file_name("smoothie-state.json");
+    let json = serde_json::to_string_pretty(jobs).expect("Failed serializing the queue's state");
+
+    fs::write(&path, json)
+        .unwrap_or_else(|e| panic!("Failed writing queue state to {}: {e}", path.display()));
+    path
+}
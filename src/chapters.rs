@@ -0,0 +1,130 @@
+use crate::cuts::{self, CutType};
+use crate::recipe::Recipe;
+use crate::timecode;
+use crate::verb;
+use crate::video::Payload;
+use num_rational::Ratio;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// How many chapter files this process wrote, `sm compare` builds its commands separately so the
+/// command index alone isn't unique
+static WRITTEN: AtomicUsize = AtomicUsize::new(0);
+
+/// ffmetadata treats these as syntax, they need to be escaped in titles
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        if matches!(c, '=' | ';' | '#' | '\\' | '\n') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// One chapter per cut, with its start/end in the output and where it came from in the source, in seconds
+pub fn chapter_times(
+    cut_points: &[(f64, f64)],
+    cut_type: CutType,
+    speed: f64,
+) -> Vec<(f64, f64, f64)> {
+    let mut elapsed = 0.0;
+
+    cut_points
+        .iter()
+        .map(|&(start, end)| match cut_type {
+            CutType::Trim => {
+                let chapter = (elapsed / speed, (elapsed + end - start) / speed, start);
+                elapsed += end - start;
+                chapter
+            }
+            CutType::Padding => (start / speed, end / speed, start),
+        })
+        .collect()
+}
+
+/// ;FFMETADATA1 with a [CHAPTER] per cut, titled `filename @ 00:01:02.500`
+pub fn ffmetadata(filename: &str, chapters: &[(f64, f64, f64)]) -> String {
+    let mut metadata = String::from(";FFMETADATA1\n");
+
+    for (start, end, source_start) in chapters {
+        let source_start = Ratio::from_integer((source_start * 1000.0).round() as i64) / 1000;
+
+        metadata.push_str(&format!(
+            "\n[CHAPTER]\nTIMEBASE=1/1000\nSTART={}\nEND={}\ntitle={}\n",
+            (start * 1000.0).round() as i64,
+            (end * 1000.0).round() as i64,
+            escape(&format!(
+                "{filename} @ {}",
+                timecode::format_clock(source_start)
+            ))
+        ));
+    }
+    metadata
+}
+
+/// Writes the chapters of a payload with multiple cuts to a temporary ffmetadata file,
+/// returns None if there's nothing worth marking or if it's disabled with `[output] chapters: no`.
+/// render_queue removes it with [`remove`] once the job is done
+pub fn write_chapters(recipe: &Recipe, payload: &Payload) -> Option<PathBuf> {
+    if !recipe.get_bool_or("output", "chapters", true) {
+        return None;
+    }
+
+    let timecodes = cuts::from_recipe(recipe)?;
+    if timecodes.len() < 2 {
+        return None;
+    }
+
+    let source_duration = payload
+        .probe
+        .format
+        .duration
+        .as_ref()
+        .and_then(|duration| duration.parse::<f64>().ok())
+        .unwrap_or(0.0);
+
+    let cut_points: Vec<(f64, f64)> = timecodes
+        .iter()
+        .map(|tc| {
+            let end = if tc.fin.trim() == "EOF" {
+                source_duration
+            } else {
+                cuts::parse_secs(&tc.fin)
+            };
+            (cuts::parse_secs(&tc.start), end)
+        })
+        .collect();
+
+    let speed = crate::audio::speed_factor(recipe, &payload.probe);
+    let chapters = chapter_times(&cut_points, CutType::from_recipe(recipe), speed);
+
+    let filename = payload
+        .in_path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let path = env::temp_dir().join(format!(
+        "smoothie-chapters-{}-{}.txt",
+        std::process::id(),
+        WRITTEN.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    fs::write(&path, ffmetadata(&filename, &chapters))
+        .unwrap_or_else(|e| panic!("Failed writing chapters to {}: {e}", path.display()));
+
+    verb!("Wrote {} chapters to {}", chapters.len(), path.display());
+
+    Some(path)
+}
+
+/// Removes a command's chapters file, if it had any
+pub fn remove(path: &Option<PathBuf>) {
+    if let Some(path) = path {
+        let _ = fs::remove_file(path);
+    }
+}
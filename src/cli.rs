@@ -1,4 +1,7 @@
+use crate::compare;
 use crate::portable;
+use crate::progress::ProgressFormat;
+use crate::provenance;
 use clap::Parser;
 use std::fs::File;
 use std::io::{Read, Write};
@@ -31,6 +34,14 @@ pub struct Arguments {
     #[clap(long, visible_alias = "outd")]
     pub outdir: Option<PathBuf>,
 
+    /// Recreate the inputs' folders (relative to their common folder) inside the output directory
+    #[clap(long, default_value_t = false)]
+    pub mirror: bool,
+
+    /// Folder the inputs' paths are mirrored relative to (implies --mirror)
+    #[clap(long)]
+    pub input_root: Option<PathBuf>,
+
     /// Overrides output to an image of frame number passed
     #[clap(long)]
     pub peek: Option<u64>,
@@ -106,6 +117,14 @@ pub struct Arguments {
     #[clap(long, default_value_t = false)]
     pub progress: bool,
 
+    /// How progress is reported, json prints one event per line for wrappers
+    #[clap(long, value_enum, default_value_t = ProgressFormat::Bar)]
+    pub progress_format: ProgressFormat,
+
+    /// Write JSON progress events to this file / named pipe instead of stdout
+    #[clap(long)]
+    pub progress_pipe: Option<PathBuf>,
+
     /// Display details about recipe, what I personally use
     #[clap(short, long, default_value_t = false)]
     pub verbose: bool,
@@ -146,6 +165,42 @@ pub struct Arguments {
     pub r#override: Option<Vec<String>>,
 }
 
+/// Renders a video with two recipes and stacks the results, e.g `sm compare -r a.ini -r b.ini input.mp4`
+#[derive(Parser, Debug, Clone)]
+#[clap(name = "sm compare")]
+pub struct CompareArguments {
+    /// Video to render with both recipes
+    pub input: PathBuf,
+
+    /// The two recipes to compare, pass it twice
+    #[clap(short, long, required = true)]
+    pub recipe: Vec<String>,
+
+    /// Only render part of the video, same format as `[runtime] timecodes:` (e.g 1:00-1:30)
+    #[clap(long)]
+    pub range: Option<String>,
+
+    /// Stack them on top of each other instead of side by side
+    #[clap(long, default_value_t = false)]
+    pub vertical: bool,
+
+    /// Where to save the comparison, defaults to next to the input
+    #[clap(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// Override recipe setting(s) of both recipes, same as sm's --override
+    #[clap(visible_alias = "ov", long, num_args = 1..)]
+    pub r#override: Option<Vec<String>>,
+
+    /// Display progress bar
+    #[clap(long, default_value_t = false)]
+    pub progress: bool,
+
+    /// Display details about recipe
+    #[clap(short, long, default_value_t = false)]
+    pub verbose: bool,
+}
+
 pub fn setup_args() -> Arguments {
     if cfg!(debug_assertions) {
         color_eyre::install().expect("Failed setting up error handler");
@@ -280,6 +335,26 @@ If you'd like help, take a screenshot of this message and your recipe and come o
             }
             std::process::exit(0);
         }
+        "inspect" => {
+            let output = match env::args().nth(2) {
+                Some(output) => PathBuf::from(output),
+                None => panic!("Usage: sm inspect <output video>"),
+            };
+
+            match provenance::read(&output) {
+                Some(provenance) => print!("{}", provenance::to_ini(&provenance)),
+                None => panic!(
+                    "No smoothie provenance found in {} (was it rendered with `[output] provenance:` on?)",
+                    output.display()
+                ),
+            }
+            std::process::exit(0);
+        }
+        "compare" => {
+            // drops `sm`, clap takes `compare` as the binary name
+            compare::compare(CompareArguments::parse_from(env::args().skip(1)));
+            std::process::exit(0);
+        }
         "!!" | "-!!" | "--!!" | "-rerun" | "--rerun" => {
             let mut file = match File::open(&last_args) {
                 Ok(file) => file,
@@ -1,106 +1,71 @@
 use std::env::current_exe;
 use which::which;
 
+use crate::backend::Kind;
 use crate::cli::Arguments;
-use crate::parse::parse_encoding_args;
+use crate::cuts::{self, CutType};
+use crate::metrics;
+use crate::provenance::Provenance;
 use crate::recipe::Recipe;
-use crate::video::Payload;
+use crate::timecode;
+use crate::video::{self, Payload};
+use crate::weighting;
 
 use crate::verb;
 use std::env;
+use std::path::PathBuf;
 
 #[derive(Debug)]
 #[allow(dead_code)]
 pub struct SmCommand {
+    pub backend: Kind,
     pub vs_path: String,
     pub vs_args: Vec<String>,
     pub payload: Payload,
-    pub ff_path: String,
+    /// What the backend outputs through (FFmpeg, mpv..), filled in by its prepare()
+    pub process_path: String,
     pub recipe: Recipe,
-    pub ff_args: Vec<String>,
+    pub process_args: Vec<String>,
     pub ffplay_path: Option<String>,
     pub ffplay_args: Option<Vec<String>>,
+    pub provenance: Option<Provenance>,
+    /// The ffmetadata file FFmpeg reads chapters from, removed once the job is done
+    pub chapters: Option<PathBuf>,
 }
 
-pub fn build_commands(args: Arguments, payloads: Vec<Payload>, recipe: Recipe) -> Vec<SmCommand> {
-    let executable: String = if args.tompv {
-        which("mpv")
-            .expect("mpv has not been installed or has not been added to PATH")
-            .display()
-            .to_string()
-    } else {
-        let ff_path = recipe.get("output", "process");
-        if ff_path == "ffmpeg" {
-            which(ff_path)
-                .expect("FFmpeg has not been installed or has not been added to PATH")
-                .display()
-                .to_string()
-        } else {
-            let is_ffmpeg: bool = ff_path.ends_with("ffmpeg") || ff_path.ends_with("ffmpeg.exe");
-            let r#override: bool = env::var("SM_ALLOW_MISC_OUTPUT") == Ok("1".to_owned());
-
-            if !is_ffmpeg && !r#override {
-                panic!("You specified an output process which does not have the filename 'ffmpeg', to override this error message please set the environment variable SM_ALLOW_MISC_OUTPUT to 1");
-            } else {
-                ff_path
-            }
-        }
-    };
-
-    let mut cmd_arguments: Vec<String> = vec![];
-    if args.tompv {
-        cmd_arguments.push("-".to_string());
+fn vs_bin() -> &'static str {
+    if cfg!(target_os = "windows") {
+        "vspipe.exe"
     } else {
-        cmd_arguments.append(
-            &mut recipe
-                .get("miscellaneous", "ffmpeg options")
-                .split(" ")
-                .map(String::from)
-                .collect(),
-        );
+        "vspipe"
     }
+}
 
-    let enc_args: Vec<String> = parse_encoding_args(&args, &recipe)
-        .split(" ")
-        .map(String::from)
-        .filter(|s| !s.is_empty())
-        .collect();
+/// --vspipe-path, then the one next to smoothie's binary, then PATH's
+pub fn find_vspipe(args: &Arguments) -> Option<PathBuf> {
+    if let Some(vspipe_path) = &args.vspipe_path {
+        return Some(vspipe_path.clone());
+    }
 
-    let cur_exe = current_exe().unwrap();
-    let cur_exe_dir = cur_exe.parent().unwrap();
-    let vs_bin = if cfg!(target_os = "windows") {
-        "vspipe.exe"
-    } else {
-        "vspipe"
-    };
-    let bin_dir_vspipe = cur_exe_dir.join(vs_bin);
-    let vspipe_in_path = which("vspipe");
-    let vs_path = (
-        if args.vspipe_path.is_some(){
-        args.vspipe_path.unwrap()}
-    else if bin_dir_vspipe.exists() {
-        verb!("Using vspipe that's in same directory as binary");
-        bin_dir_vspipe
-    } else if vspipe_in_path.is_ok() {
-        verb!("Using VSPipe from PATH");
-        vspipe_in_path.unwrap()
+    let bin_dir_vspipe = current_exe().ok()?.parent()?.join(vs_bin());
+    if bin_dir_vspipe.exists() {
+        Some(bin_dir_vspipe)
     } else {
-        panic!("vspipe binary in path/bin dir not found");
-    })
-    .display()
-    .to_string();
+        which("vspipe").ok()
+    }
+}
 
-    let vpy_path = if args.vpy.exists() {
-        args.vpy
-    } else if cur_exe_dir.parent().unwrap().join(&args.vpy).exists() {
-        cur_exe_dir.parent().unwrap().join(&args.vpy)
-    } else {
-        panic!(
-            "jamba.vpy not found, expected {:?}",
-            cur_exe_dir.parent().unwrap().join(&args.vpy)
-        );
-    };
+/// --vpy as given, or relative to the folder smoothie's bin dir is in
+pub fn find_vpy(args: &Arguments) -> Option<PathBuf> {
+    if args.vpy.exists() {
+        return Some(args.vpy.clone());
+    }
+    let vpy_path = current_exe().ok()?.parent()?.parent()?.join(&args.vpy);
+    vpy_path.exists().then_some(vpy_path)
+}
 
+/// The recipe as jamba.vpy reads it from `--arg recipe=`
+pub fn recipe_arg(recipe: &Recipe) -> String {
     /*
         scuffed, but works
 
@@ -108,179 +73,131 @@ pub fn build_commands(args: Arguments, payloads: Vec<Payload>, recipe: Recipe) -
 
         old one : let rc_string = serde_json::to_string(&recipe).expect("Failed serializing recipe to JSON");
     */
-    let rc_string = (format!("{:?}", &recipe)).replace("Recipe { data: {", "{ \"data\": {");
+    let rc_string = (format!("{:?}", recipe)).replace("Recipe { data: {", "{ \"data\": {");
+    format!("recipe={rc_string:?}")
+}
 
+pub fn build_commands(args: Arguments, payloads: Vec<Payload>, recipe: Recipe) -> Vec<SmCommand> {
+    let backend = Kind::select(&args, &recipe);
+    let renderer = backend.create();
 
-    let vs_args = vec![
-        // "--progress".to_owned(),
-        "--container".to_owned(),
-        "y4m".to_owned(),
-        "-".to_owned(),
-        vpy_path.display().to_string(),
-        "--arg".to_owned(),
-        format!("recipe={rc_string:?}"),
-    ];
+    let cut_type = CutType::from_args(&args);
+    metrics::validate(&recipe);
 
-    let mut ret: Vec<SmCommand> = vec![];
+    let cur_exe = current_exe().unwrap();
+    let cur_exe_dir = cur_exe.parent().unwrap();
 
-    for payload in payloads {
-        let mut cur_vs_args = vs_args.clone();
+    let (vs_path, vs_args) = if backend.uses_vapoursynth() {
+        let vs_path = match find_vspipe(&args) {
+            Some(vs_path) => vs_path,
+            // jamba.vpy is evaluated in-process, vspipe's args are only read back
+            None if cfg!(feature = "vapoursynth") => PathBuf::from(vs_bin()),
+            None => panic!("vspipe binary in path/bin dir not found"),
+        };
+        verb!("Using VSPipe at {}", vs_path.display());
+
+        let vpy_path = find_vpy(&args).unwrap_or_else(|| {
+            panic!(
+                "jamba.vpy not found, expected {:?}",
+                cur_exe_dir.parent().unwrap().join(&args.vpy)
+            )
+        });
 
-        cur_vs_args.append(&mut vec![
-            "--arg".to_owned(),
-            format!("input_video={}", payload.in_path.display()),
-        ]);
-        if let Some(timecodes) = payload.timecodes.clone() {
-            let json_timecodes =
-                serde_json::to_string(&timecodes).expect("Failed serializing timecodes to JSON");
+        let vs_args = vec![
+            // "--progress".to_owned(),
+            "--container".to_owned(),
+            "y4m".to_owned(),
+            "-".to_owned(),
+            vpy_path.display().to_string(),
+        ];
+        (vs_path.display().to_string(), vs_args)
+    } else {
+        verb!("Rendering with FFmpeg's filters, VapourSynth isn't used");
+        (String::new(), vec![])
+    };
 
-            cur_vs_args.append(&mut vec![
-                "--arg".to_owned(),
-                format!("timecodes={json_timecodes:?}"),
-            ]);
-        }
+    let mut ret: Vec<SmCommand> = vec![];
 
-        if payload.in_path == payload.out_path {
-            panic!("Output path has same path as input")
+    for (index, payload) in payloads.into_iter().enumerate() {
+        // cuts are read by both jamba.vpy (video) and build_audio_args from [runtime]
+        let mut recipe = recipe.clone();
+        if let Some(timecodes) = &payload.timecodes {
+            recipe.insert_value("runtime", "timecodes".to_owned(), cuts::to_runtime(timecodes));
+            recipe.insert_value(
+                "runtime",
+                "cut type".to_owned(),
+                cut_type.as_str().to_owned(),
+            );
+        } else if let Some(timecodes) = cuts::from_recipe(&recipe) {
+            let ctx = timecode::Context::from_probe(&payload.probe);
+            let ranges = timecode::normalize(&timecodes, &ctx).unwrap_or_else(|e| {
+                panic!(
+                    "Invalid `[runtime] timecodes:` for {}: {e}",
+                    payload.in_path.display()
+                )
+            });
+            recipe.insert_value(
+                "runtime",
+                "timecodes".to_owned(),
+                cuts::to_runtime(&timecode::to_timecodes(&ranges)),
+            );
+            if recipe.get_option("runtime", "cut type").is_none() {
+                recipe.insert_value("runtime", "cut type".to_owned(), "trim".to_owned());
+            }
         }
 
-        let mut cur_cmd_arguments = cmd_arguments.clone();
+        let mut cur_vs_args = vs_args.clone();
 
-        if args.tompv {
-            // nothing to do, but this still needs to step in to break out the if chain
-            if let Some(p) = args.peek {
-                // duplicate sowwy :33
-                cur_vs_args.append(&mut vec![
-                    "--start".to_owned(),
-                    p.to_string(),
-                    "--end".to_owned(),
-                    p.to_string()
-                ]);
-            }
-        } else if args.tonull {
-            cur_cmd_arguments.append(&mut vec![
-                "-i".to_owned(),
-                payload.in_path.display().to_string(),
-                "-f".to_owned(),
-                "null".to_owned(),
-                "NUL".to_owned(),
+        if backend.uses_vapoursynth() {
+            cur_vs_args.append(&mut vec![
+                "--arg".to_owned(),
+                recipe_arg(&recipe),
+                "--arg".to_owned(),
+                format!("input_video={}", payload.in_path.display()),
             ]);
-            // cur_cmd_arguments.push(format!(" -i {:?} -f null NUL ", payload.in_path));
-        } else {
-            if let Some(p) = args.peek {
+            if let Some(timecodes) = payload.timecodes.clone() {
+                let json_timecodes =
+                    serde_json::to_string(&timecodes).expect("Failed serializing timecodes to JSON");
+
                 cur_vs_args.append(&mut vec![
-                    "--start".to_owned(),
-                    p.to_string(),
-                    "--end".to_owned(),
-                    p.to_string()
+                    "--arg".to_owned(),
+                    format!("timecodes={json_timecodes:?}"),
                 ]);
-            } else if args.stripaudio {
-                cur_cmd_arguments.append(&mut enc_args.clone());
-            } else {
-                let mut audio_tracks = 0;
-                for stream in &payload.probe.streams {
-                    if stream.codec_type == Some("audio".to_owned()) {
-                        audio_tracks += 1;
-                    }
-                }
-                let timecodes = recipe.get_option("runtime", "timecodes");
-
-                if audio_tracks > 0 && timecodes.is_some() && timecodes != Some("".to_string()) {
-                    let timecodes = timecodes.unwrap();
-                    let mut filter_complex = String::new();
-
-                    for track_number in 0..audio_tracks {
-                        let mut merge = String::new();
-                        let mut iter = 1;
-                        for timecode in timecodes.split(";") {
-                            let (start, end) = timecode
-                                .split_once("-")
-                                .expect("runtine timecode split failed");
-
-                            filter_complex.push_str(format!("[1:a:{track_number}]atrim=start={start}:end={end},asetpts=PTS-STARTPTS[a{iter}{track_number}];").as_str());
-                            merge.push_str(format!("[a{iter}{track_number}]").as_str());
-                            iter += 1;
-                        }
-                        iter -= 1;
-                        filter_complex.push_str(merge.as_str());
-                        filter_complex.push_str(
-                            format!("concat=n={iter}:v=0:a=1[outa{track_number}];").as_str(),
-                        );
-                    }
-
-                    cur_cmd_arguments.append(&mut vec![
-                        "-i".to_owned(),
-                        format!("{}", payload.in_path.display().to_string()),
-                        "-filter_complex".to_owned(),
-                        filter_complex,
-                        "-map".to_owned(),
-                        "0:v".to_owned(),
-                    ]);
-
-                    for track_number in 0..audio_tracks {
-                        cur_cmd_arguments.append(&mut vec![
-                            "-map".to_owned(),
-                            format!("[outa{track_number}]").to_owned(),
-                        ]);
-                    }
-
-                } else {
-                    cur_cmd_arguments.append(&mut vec![
-                        "-i".to_owned(),
-                        format!("{}", payload.in_path.display().to_string()),
-                        "-map".to_owned(),
-                        "0:v".to_owned(),
-                        "-map".to_owned(),
-                        "1:a?".to_owned(),
-                    ]);
-                }
             }
-            cur_cmd_arguments.append(&mut enc_args.clone());
-            cur_cmd_arguments.push(payload.out_path.display().to_string());
+        }
 
-            if recipe.get_bool("preview window", "enabled") {
-                let mut ffmpeg_preview_output: Vec<String> = recipe
-                    .get("preview window", "output args")
-                    .split(" ")
-                    .map(String::from)
-                    .collect();
+        if payload.in_path == payload.out_path {
+            panic!("Output path has same path as input")
+        }
 
-                cur_cmd_arguments.append(&mut ffmpeg_preview_output);
+        if let Some(fps) = video::blending_fps(&recipe, &payload.probe) {
+            let fps = *fps.numer() as f64 / *fps.denom() as f64;
+            match weighting::from_recipe(&recipe, fps) {
+                Some(Ok(weights)) => verb!(
+                    "Blending {fps:.3}fps with {} weights: {}",
+                    weights.len(),
+                    weighting::format(&weights)
+                ),
+                Some(Err(e)) => verb!("Could not compute the blending weights: {e}"),
+                None => {}
             }
         }
 
-        let (ffplay_path, ffplay_args) =
-            if recipe.get_bool("preview window", "enabled") && !args.tompv && args.peek.is_none() {
-                let mut ffplay_path = recipe.get("preview window", "process");
-                if ffplay_path == "ffplay" {
-                    ffplay_path = which(ffplay_path)
-                    .expect(
-                        "FFplay (previewer) has not been installed or has not been added to PATH",
-                    )
-                    .display()
-                    .to_string()
-                };
-                let ffplay_args: Vec<String> = recipe
-                    .get("miscellaneous", "ffplay options")
-                    .split(" ")
-                    .map(String::from)
-                    .collect();
-
-                (Some(ffplay_path), Some(ffplay_args))
-            } else {
-                (None, None)
-            };
-        // dbg!(&cur_cmd_arguments);
-        ret.push(SmCommand {
+        let mut cmd = SmCommand {
+            backend,
             payload,
-            ff_path: executable.clone(),
-            ff_args: cur_cmd_arguments,
-            recipe: recipe.clone(),
-            ffplay_path,
-            ffplay_args,
+            process_path: String::new(),
+            process_args: vec![],
+            recipe,
+            ffplay_path: None,
+            ffplay_args: None,
             vs_path: vs_path.clone(),
-            vs_args: cur_vs_args.clone(),
-        });
+            vs_args: cur_vs_args,
+            provenance: None,
+            chapters: None,
+        };
+        renderer.prepare(&mut cmd, &args, index);
+        ret.push(cmd);
     }
 
     ret
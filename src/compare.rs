@@ -0,0 +1,358 @@
+//! `sm compare -r a.ini -r b.ini input.mp4`: renders a clip with two recipes,
+//! then stacks both renders into one video labelled with what differs between them
+
+use crate::cli::{Arguments, CompareArguments};
+use crate::cmd::{self, SmCommand};
+use crate::parse::parse_encoding_args;
+use crate::recipe::{self, Recipe};
+use crate::render::render_queue;
+use crate::utils::verbosity_init;
+use crate::{backend, verb, video};
+use clap::Parser;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Past this many differing keys the label only says how many more there are
+const MAX_LABEL_LINES: usize = 8;
+
+pub fn compare(cargs: CompareArguments) {
+    if cargs.recipe.len() != 2 {
+        panic!("sm compare takes two recipes, e.g `sm compare -r a.ini -r b.ini input.mp4`");
+    }
+    if !cargs.input.exists() {
+        panic!("{} does not exist", cargs.input.display());
+    }
+    let stem = cargs
+        .input
+        .file_stem()
+        .expect("Failed getting the input's filename")
+        .to_string_lossy()
+        .to_string();
+
+    let mut sides: Vec<(Arguments, Recipe, SmCommand)> = vec![];
+    for (side, rc) in ["a", "b"].iter().zip(&cargs.recipe) {
+        let mut args = Arguments::parse_from(["sm", "--recipe", rc]);
+        args.input = vec![cargs.input.clone()];
+        args.output = Some(
+            env::temp_dir()
+                .join(format!("{stem} ~ compare {side}.mkv"))
+                .display()
+                .to_string(),
+        );
+        args.verbose = cargs.verbose;
+        args.progress = cargs.progress;
+        args.r#override = overrides(&cargs);
+
+        let (recipe, _metadata) = recipe::get_recipe(&mut args);
+        verbosity_init(
+            args.verbose,
+            recipe.get_bool("miscellaneous", "always verbose"),
+        );
+
+        let payloads = video::resolve_input(&mut args, &recipe);
+        let mut commands = cmd::build_commands(args.clone(), payloads, recipe.clone());
+        let command = commands
+            .pop()
+            .unwrap_or_else(|| panic!("Nothing to render with {rc}"));
+        if !command.backend.writes_output() {
+            panic!("sm compare needs both renders as files, {rc} uses a backend that doesn't write any (`[output] backend:`)");
+        }
+        sides.push((args, recipe, command));
+    }
+
+    let (labels_a, labels_b) = differences(&sides[0].1, &sides[1].1);
+    let labels = [
+        label_lines(&sides[0].0.recipe, labels_a),
+        label_lines(&sides[1].0.recipe, labels_b),
+    ];
+
+    let mut renders = Renders(vec![]);
+    let (args, recipe) = (sides[0].0.clone(), sides[0].1.clone());
+    let ffmpeg = backend::ffmpeg_path(&sides[0].2.recipe).unwrap_or_else(|e| panic!("{e}"));
+    for (_, _, command) in sides {
+        let output = command.payload.out_path.clone();
+        renders.0.push(output.clone());
+        render_queue(vec![command], cargs.progress, None);
+
+        if !output.exists() {
+            panic!("Rendering {} failed, nothing to compare", output.display());
+        }
+    }
+
+    let output = cargs
+        .output
+        .clone()
+        .unwrap_or_else(|| cargs.input.with_file_name(format!("{stem} ~ compare.mp4")));
+    stack(
+        &ffmpeg,
+        &renders.0,
+        &labels,
+        cargs.vertical,
+        &args,
+        &recipe,
+        &output,
+    );
+    drop(renders);
+    println!("Saved comparison to {}", output.display());
+}
+
+/// The temporary renders, removed once dropped (unwinding included)
+struct Renders(Vec<PathBuf>);
+
+impl Drop for Renders {
+    fn drop(&mut self) {
+        for render in &self.0 {
+            let _ = fs::remove_file(render);
+        }
+    }
+}
+
+/// --override for both recipes, with --range as a trim
+fn overrides(cargs: &CompareArguments) -> Option<Vec<String>> {
+    let mut overrides = cargs.r#override.clone().unwrap_or_default();
+    if let Some(range) = &cargs.range {
+        overrides.push(format!("runtime;timecodes;{range}"));
+        overrides.push("runtime;cut type;trim".to_owned());
+    }
+    (!overrides.is_empty()).then_some(overrides)
+}
+
+/// `section: key: value` lines for every value that's not the same in both recipes.
+/// Sections that are disabled in both are skipped, their keys don't change anything
+fn differences(a: &Recipe, b: &Recipe) -> (Vec<String>, Vec<String>) {
+    let (mut only_a, mut only_b) = (vec![], vec![]);
+
+    let mut sections: Vec<&String> = a.data.keys().collect();
+    sections.extend(
+        b.data
+            .keys()
+            .filter(|section| !a.data.contains_key(*section)),
+    );
+
+    for section in sections {
+        let disabled = |recipe: &Recipe| {
+            recipe.get_option(section, "enabled").is_some()
+                && !recipe.get_bool_or(section, "enabled", false)
+        };
+        if disabled(a) && disabled(b) {
+            continue;
+        }
+
+        let mut keys: Vec<&String> = vec![];
+        for recipe in [a, b] {
+            if let Some(values) = recipe.data.get(section) {
+                for key in values.keys() {
+                    if !keys.contains(&key) {
+                        keys.push(key);
+                    }
+                }
+            }
+        }
+
+        for key in keys {
+            let (value_a, value_b) = (a.get_option(section, key), b.get_option(section, key));
+            if value_a.as_deref().map(str::trim) == value_b.as_deref().map(str::trim) {
+                continue;
+            }
+            let line = |value: Option<String>| {
+                format!(
+                    "{section}: {key}: {}",
+                    value.unwrap_or_else(|| "(unset)".to_owned()).trim()
+                )
+            };
+            only_a.push(line(value_a));
+            only_b.push(line(value_b));
+        }
+    }
+    (only_a, only_b)
+}
+
+/// The recipe's filename followed by its differences
+fn label_lines(recipe_path: &str, differences: Vec<String>) -> Vec<String> {
+    let name = Path::new(recipe_path)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| recipe_path.to_owned());
+
+    let mut lines = vec![name];
+    if differences.is_empty() {
+        lines.push("(same recipe)".to_owned());
+    }
+    let more = differences.len().saturating_sub(MAX_LABEL_LINES);
+    lines.extend(differences.into_iter().take(MAX_LABEL_LINES));
+    if more > 0 {
+        lines.push(format!("... and {more} more"));
+    }
+    lines
+}
+
+/// drawtext's `text`, escaped for its own options and then for the filtergraph it's in
+fn escape_drawtext(text: &str) -> String {
+    let escape = |text: &str, special: &[char]| {
+        let mut escaped = String::new();
+        for c in text.chars() {
+            if special.contains(&c) {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+        }
+        escaped
+    };
+    escape(
+        &escape(text, &['\\', '\'', ':']),
+        &['\\', '\'', '[', ']', ',', ';'],
+    )
+}
+
+/// Puts both renders next to each other (or on top of each other), matching the first's size
+/// and the highest frame rate of the two, with audio from the first
+fn stack(
+    ffmpeg: &str,
+    renders: &[PathBuf],
+    labels: &[Vec<String>; 2],
+    vertical: bool,
+    args: &Arguments,
+    recipe: &Recipe,
+    output: &Path,
+) {
+    let probes: Vec<_> = renders
+        .iter()
+        .map(|render| {
+            video::probe_video(render)
+                .unwrap_or_else(|| panic!("Failed probing {}", render.display()))
+        })
+        .collect();
+
+    let size = |probe: &ffprobe::FfProbe| {
+        probe
+            .streams
+            .iter()
+            .find(|stream| stream.codec_type == Some("video".to_owned()))
+            .and_then(|stream| Some((stream.width?, stream.height?)))
+            .expect("Failed getting the render's resolution")
+    };
+    let (width, height) = size(&probes[0]);
+    let fps = probes
+        .iter()
+        .filter_map(video::source_fps)
+        .fold(0.0, f64::max);
+    let font_size = (height / 30).max(12);
+
+    let mut filters: Vec<String> = vec![];
+    for (i, lines) in labels.iter().enumerate() {
+        let mut chain = vec![format!("fps={fps}")];
+        if i == 1 && size(&probes[1]) != (width, height) {
+            chain.push(if vertical {
+                format!("scale={width}:-2")
+            } else {
+                format!("scale=-2:{height}")
+            });
+        }
+        for (line, text) in lines.iter().enumerate() {
+            chain.push(format!(
+                "drawtext=text={}:expansion=none:x=10:y={}:fontsize={font_size}:fontcolor=white:box=1:boxcolor=black@0.6:boxborderw=4",
+                escape_drawtext(text),
+                10 + line as i64 * (font_size + 10)
+            ));
+        }
+        filters.push(format!("[{i}:v]{}[v{i}]", chain.join(",")));
+    }
+    filters.push(format!(
+        "[v0][v1]{}=inputs=2[v]",
+        if vertical { "vstack" } else { "hstack" }
+    ));
+
+    let mut ff_args: Vec<String> = vec!["-hide_banner".to_owned(), "-stats".to_owned()];
+    for render in renders {
+        ff_args.append(&mut vec!["-i".to_owned(), render.display().to_string()]);
+    }
+    ff_args.append(&mut vec![
+        "-filter_complex".to_owned(),
+        filters.join(";"),
+        "-map".to_owned(),
+        "[v]".to_owned(),
+        "-map".to_owned(),
+        "0:a?".to_owned(),
+    ]);
+    ff_args.extend(
+        parse_encoding_args(args, recipe)
+            .split(' ')
+            .filter(|arg| !arg.is_empty())
+            .map(String::from),
+    );
+    ff_args.append(&mut vec!["-y".to_owned(), output.display().to_string()]);
+
+    verb!("FF args: {}", ff_args.join(" "));
+    let status = Command::new(ffmpeg)
+        .args(&ff_args)
+        .status()
+        .expect("Failed in spawning FFmpeg child");
+
+    if !status.success() {
+        panic!("FFmpeg failed stacking the renders ({status})");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recipe(values: &[(&str, &str, &str)]) -> Recipe {
+        let mut recipe = Recipe::new();
+        for (section, key, value) in values {
+            recipe.insert_value(section, key.to_string(), value.to_string());
+        }
+        recipe
+    }
+
+    #[test]
+    fn lists_what_differs() {
+        let a = recipe(&[
+            ("interpolation", "enabled", "no"),
+            ("interpolation", "fps", "960"),
+            ("frame blending", "fps", "60"),
+            ("frame blending", "intensity", "1.0 "),
+        ]);
+        let b = recipe(&[
+            ("interpolation", "enabled", "no"),
+            ("interpolation", "fps", "1920"),
+            ("frame blending", "fps", "30"),
+            ("frame blending", "intensity", "1.0"),
+            ("flowblur", "amount", "50"),
+        ]);
+
+        let (only_a, only_b) = differences(&a, &b);
+        assert_eq!(
+            only_a,
+            ["frame blending: fps: 60", "flowblur: amount: (unset)"]
+        );
+        assert_eq!(only_b, ["frame blending: fps: 30", "flowblur: amount: 50"]);
+    }
+
+    #[test]
+    fn section_disabled_in_one_is_listed() {
+        let a = recipe(&[
+            ("interpolation", "enabled", "no"),
+            ("interpolation", "fps", "960"),
+        ]);
+        let b = recipe(&[
+            ("interpolation", "enabled", "yes"),
+            ("interpolation", "fps", "960"),
+        ]);
+
+        let (only_a, only_b) = differences(&a, &b);
+        assert_eq!(only_a, ["interpolation: enabled: no"]);
+        assert_eq!(only_b, ["interpolation: enabled: yes"]);
+    }
+
+    #[test]
+    fn escapes_drawtext() {
+        assert_eq!(escape_drawtext("fps: 60"), r"fps\\: 60");
+        assert_eq!(escape_drawtext("it's"), r"it\\\'s");
+        assert_eq!(escape_drawtext("1,2"), r"1\,2");
+        assert_eq!(escape_drawtext("[a];b"), r"\[a\]\;b");
+        assert_eq!(escape_drawtext("plain text"), "plain text");
+    }
+}
@@ -0,0 +1,329 @@
+use crate::cli::Arguments;
+use crate::recipe::Recipe;
+use crate::video::Timecodes;
+use ffprobe::FfProbe;
+
+/// What to do with the parts of the video that are not in any cut, see jamba.vpy's `[runtime] cut type`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CutType {
+    /// Join all cuts one after the other
+    Trim,
+    /// Keep the source's length, anything outside of the cuts is black / silent
+    Padding,
+}
+
+impl CutType {
+    /// --padding keeps the length, anything else (--trim or nothing) joins the cuts
+    pub fn from_args(args: &Arguments) -> CutType {
+        if args.padding {
+            CutType::Padding
+        } else {
+            CutType::Trim
+        }
+    }
+
+    pub fn from_recipe(recipe: &Recipe) -> CutType {
+        match recipe.get_option("runtime", "cut type").as_deref() {
+            Some("padding") => CutType::Padding,
+            Some("trim") | Some("") | None => CutType::Trim,
+            Some(other) => {
+                panic!("Unknown `[runtime] cut type:` {other:?}, expected trim or padding")
+            }
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CutType::Trim => "trim",
+            CutType::Padding => "padding",
+        }
+    }
+}
+
+/// Same as jamba.vpy's get_sec, `1:02.5` => 62.5
+pub fn parse_secs(timecode: &str) -> f64 {
+    let timecode = timecode.trim();
+
+    let (whole, spare) = match timecode.split_once('.') {
+        Some((whole, fraction)) => (
+            whole,
+            format!("0.{fraction}")
+                .parse::<f64>()
+                .unwrap_or_else(|_| panic!("Failed parsing timecode {timecode:?}")),
+        ),
+        None => (timecode, 0.0),
+    };
+
+    whole
+        .split(':')
+        .rev()
+        .enumerate()
+        .map(|(i, part)| {
+            part.parse::<f64>()
+                .unwrap_or_else(|_| panic!("Failed parsing timecode {timecode:?}"))
+                * 60f64.powi(i as i32)
+        })
+        .sum::<f64>()
+        + spare
+}
+
+/// `EOF` can be used as the end of the last cut
+fn parse_end(fin: &str, source_duration: f64) -> f64 {
+    if fin.trim() == "EOF" {
+        source_duration
+    } else {
+        parse_secs(fin)
+    }
+}
+
+/// Formats cuts the way `[runtime] timecodes:` expects them, e.g `0:01-0:05;1:00-EOF`
+pub fn to_runtime(timecodes: &[Timecodes]) -> String {
+    timecodes
+        .iter()
+        .map(|tc| format!("{}-{}", tc.start.trim(), tc.fin.trim()))
+        .collect::<Vec<String>>()
+        .join(";")
+}
+
+pub fn from_runtime(runtime: &str) -> Vec<Timecodes> {
+    runtime
+        .split(';')
+        .filter(|timecode| !timecode.trim().is_empty())
+        .map(|timecode| {
+            let (start, fin) = timecode
+                .split_once('-')
+                .unwrap_or_else(|| panic!("Failed splitting runtime timecode {timecode:?}"));
+            Timecodes {
+                start: start.trim().to_owned(),
+                fin: fin.trim().to_owned(),
+            }
+        })
+        .collect()
+}
+
+/// Cuts from `[runtime] timecodes:`, set by --json payloads or --override
+pub fn from_recipe(recipe: &Recipe) -> Option<Vec<Timecodes>> {
+    recipe
+        .get_option("runtime", "timecodes")
+        .filter(|timecodes| !timecodes.trim().is_empty())
+        .map(|timecodes| from_runtime(&timecodes))
+}
+
+/// Length of the output in seconds (before timescale), given the source's length
+pub fn expected_duration(timecodes: &[Timecodes], cut_type: CutType, source_duration: f64) -> f64 {
+    match cut_type {
+        CutType::Padding => source_duration,
+        CutType::Trim => timecodes
+            .iter()
+            .map(|tc| {
+                let start = parse_secs(&tc.start).min(source_duration);
+                let end = parse_end(&tc.fin, source_duration).min(source_duration);
+                (end - start).max(0.0)
+            })
+            .sum(),
+    }
+}
+
+/// Filter graph part that cuts audio track `n` of the source (`[1:a:n]`) into `[cut{n}]`
+pub fn audio_filter(
+    n: usize,
+    timecodes: &[Timecodes],
+    cut_type: CutType,
+    source_duration: f64,
+) -> String {
+    match cut_type {
+        CutType::Trim => {
+            let mut filter = String::new();
+            let mut merge = String::new();
+
+            for (i, tc) in timecodes.iter().enumerate() {
+                let start = parse_secs(&tc.start);
+                let end = parse_end(&tc.fin, source_duration);
+
+                filter.push_str(&format!(
+                    "[1:a:{n}]atrim=start={start}:end={end},asetpts=PTS-STARTPTS[c{n}_{i}];"
+                ));
+                merge.push_str(&format!("[c{n}_{i}]"));
+            }
+            filter.push_str(&format!(
+                "{merge}concat=n={}:v=0:a=1[cut{n}];",
+                timecodes.len()
+            ));
+            filter
+        }
+        CutType::Padding => {
+            let kept: Vec<String> = timecodes
+                .iter()
+                .map(|tc| {
+                    format!(
+                        "between(t,{},{})",
+                        parse_secs(&tc.start),
+                        parse_end(&tc.fin, source_duration)
+                    )
+                })
+                .collect();
+
+            format!(
+                "[1:a:{n}]volume=0:enable='not({})'[cut{n}];",
+                kept.join("+")
+            )
+        }
+    }
+}
+
+/// `-vf` part that cuts the source's video the way jamba.vpy would, for when it's not used
+pub fn video_filter(timecodes: &[Timecodes], cut_type: CutType, source_duration: f64) -> String {
+    let kept: Vec<String> = timecodes
+        .iter()
+        .map(|tc| {
+            format!(
+                "between(t,{},{})",
+                parse_secs(&tc.start),
+                parse_end(&tc.fin, source_duration)
+            )
+        })
+        .collect();
+
+    match cut_type {
+        CutType::Trim => format!("select='{}',setpts=N/FRAME_RATE/TB", kept.join("+")),
+        CutType::Padding => format!("drawbox=c=black:t=fill:enable='not({})'", kept.join("+")),
+    }
+}
+
+/// Output length in seconds as it'll be reported by FFmpeg, cuts and timescale included
+pub fn output_duration(recipe: &Recipe, source_duration: f64, probe: &FfProbe) -> f64 {
+    let duration = match from_recipe(recipe) {
+        Some(timecodes) => {
+            expected_duration(&timecodes, CutType::from_recipe(recipe), source_duration)
+        }
+        None => source_duration,
+    };
+
+    duration / crate::audio::speed_factor(recipe, probe)
+}
+
+/// Which frame of the cut video the source's `frame` is, sliced the way jamba.vpy does it.
+/// None if it's in none of the cuts (with padding it's still there, only blacked out)
+pub fn cut_frame(
+    timecodes: &[Timecodes],
+    cut_type: CutType,
+    fps: f64,
+    source_duration: f64,
+    frame: u64,
+) -> Option<u64> {
+    if cut_type == CutType::Padding {
+        return Some(frame);
+    }
+
+    let to_frame = |secs: f64| (secs * fps).floor() as u64;
+    let mut offset = 0;
+    for tc in timecodes {
+        let start = to_frame(parse_secs(&tc.start));
+        let end = to_frame(parse_end(&tc.fin, source_duration));
+        if (start..end).contains(&frame) {
+            return Some(offset + frame - start);
+        }
+        offset += end.saturating_sub(start);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tc(start: &str, fin: &str) -> Timecodes {
+        Timecodes {
+            start: start.to_owned(),
+            fin: fin.to_owned(),
+        }
+    }
+
+    #[test]
+    fn parses_timecodes() {
+        assert_eq!(parse_secs("5"), 5.0);
+        assert_eq!(parse_secs("1:02.5"), 62.5);
+        assert_eq!(parse_secs("01:00:01"), 3601.0);
+        assert_eq!(parse_end("EOF", 42.0), 42.0);
+    }
+
+    #[test]
+    fn runtime_roundtrip() {
+        let cuts = vec![tc("0:01", "0:05"), tc("1:00", "EOF")];
+        let runtime = to_runtime(&cuts);
+
+        assert_eq!(runtime, "0:01-0:05;1:00-EOF");
+
+        let parsed = from_runtime(&runtime);
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[1].start, "1:00");
+        assert_eq!(parsed[1].fin, "EOF");
+    }
+
+    #[test]
+    fn trim_duration_is_sum_of_cuts() {
+        let cuts = vec![tc("1", "3"), tc("10", "15.5"), tc("55", "EOF")];
+
+        assert_eq!(expected_duration(&cuts, CutType::Trim, 60.0), 12.5);
+    }
+
+    #[test]
+    fn trim_duration_is_clamped_to_source() {
+        let cuts = vec![tc("50", "70"), tc("80", "90")];
+
+        assert_eq!(expected_duration(&cuts, CutType::Trim, 60.0), 10.0);
+    }
+
+    #[test]
+    fn padding_keeps_source_duration() {
+        let cuts = vec![tc("1", "3"), tc("10", "15")];
+
+        assert_eq!(expected_duration(&cuts, CutType::Padding, 60.0), 60.0);
+    }
+
+    #[test]
+    fn cut_frames_follow_the_cuts_order() {
+        let cuts = vec![tc("10", "12"), tc("1", "3")];
+
+        assert_eq!(cut_frame(&cuts, CutType::Trim, 10.0, 60.0, 105), Some(5));
+        assert_eq!(cut_frame(&cuts, CutType::Trim, 10.0, 60.0, 10), Some(20));
+        assert_eq!(cut_frame(&cuts, CutType::Trim, 10.0, 60.0, 50), None);
+        assert_eq!(cut_frame(&cuts, CutType::Padding, 10.0, 60.0, 50), Some(50));
+    }
+
+    #[test]
+    fn trim_audio_filter_concats_cuts() {
+        let cuts = vec![tc("1", "3"), tc("10", "EOF")];
+
+        assert_eq!(
+            audio_filter(1, &cuts, CutType::Trim, 20.0),
+            "[1:a:1]atrim=start=1:end=3,asetpts=PTS-STARTPTS[c1_0];\
+             [1:a:1]atrim=start=10:end=20,asetpts=PTS-STARTPTS[c1_1];\
+             [c1_0][c1_1]concat=n=2:v=0:a=1[cut1];"
+        );
+    }
+
+    #[test]
+    fn padding_audio_filter_mutes_gaps() {
+        let cuts = vec![tc("1", "3"), tc("0:10", "0:12.5")];
+
+        assert_eq!(
+            audio_filter(0, &cuts, CutType::Padding, 20.0),
+            "[1:a:0]volume=0:enable='not(between(t,1,3)+between(t,10,12.5))'[cut0];"
+        );
+    }
+
+    #[test]
+    fn video_filter_matches_audio_cuts() {
+        let cuts = vec![tc("1", "3"), tc("10", "EOF")];
+
+        assert_eq!(
+            video_filter(&cuts, CutType::Trim, 20.0),
+            "select='between(t,1,3)+between(t,10,20)',setpts=N/FRAME_RATE/TB"
+        );
+        assert_eq!(
+            video_filter(&cuts, CutType::Padding, 20.0),
+            "drawbox=c=black:t=fill:enable='not(between(t,1,3)+between(t,10,20))'"
+        );
+    }
+}
@@ -1,31 +1,104 @@
+use crate::progress;
+use colored::Colorize;
 use kdam::{tqdm, BarExt, Column, RichProgress};
-use regex::{Captures, Regex};
 use std::{
-    io::{stderr, BufRead, BufReader, Error, ErrorKind, IsTerminal},
-    num::ParseIntError,
-    process::ChildStderr,
+    io::{stderr, BufRead, BufReader, Error, IsTerminal, Read},
+    process::Child,
+    sync::mpsc::{channel, Receiver},
     thread,
-    time::Duration,
 };
-use colored::Colorize;
 
-fn new_error(msg: &str) -> Error {
-    Error::new(ErrorKind::Other, msg)
+/// The level of a line FFmpeg logged with `-loglevel level+..`, which comes after what logged it:
+/// `[h264 @ 0x55d0] [warning] ..`
+fn level(line: &str) -> Option<&str> {
+    let mut rest = line.trim_start();
+    while let Some((tag, after)) = rest.strip_prefix('[').and_then(|tag| tag.split_once("] ")) {
+        if !tag.contains(' ') {
+            return Some(tag);
+        }
+        rest = after;
+    }
+    None
 }
 
-fn time_to_secs(x: &Captures) -> Result<usize, ParseIntError> {
-    let hours = x.get(1).unwrap().as_str().parse::<usize>()?;
-    let minutes = x.get(2).unwrap().as_str().parse::<usize>()?;
-    let seconds = x.get(3).unwrap().as_str().parse::<usize>()?;
-    Ok((((hours * 60) + minutes) * 60) + seconds)
+/// Whether a line FFmpeg logged is a warning, only known with `-loglevel level+..`
+pub fn is_warning(line: &str) -> bool {
+    level(line) == Some("warning")
 }
 
-pub fn ffmpeg(ffmpeg: ChildStderr, duration: usize, fps: Option<i32>) -> Result<(), Error> {
+/// Makes FFmpeg prefix what it logs with its level, so warnings and errors can be told apart.
+/// `forced` replaces the recipe's own `-loglevel`, which is kept (minus its flags) otherwise
+pub fn print_levels(ff_args: &mut Vec<String>, forced: Option<&str>) {
+    // FFmpeg's default
+    let mut level = "info".to_owned();
+    while let Some(i) = ff_args
+        .iter()
+        .position(|arg| arg == "-loglevel" || arg == "-v")
+    {
+        if let Some(value) = ff_args.get(i + 1) {
+            level = value.rsplit('+').next().unwrap_or(value).to_owned();
+        }
+        ff_args.drain(i..(i + 2).min(ff_args.len()));
+    }
+    ff_args.splice(
+        0..0,
+        [
+            "-hide_banner".to_owned(),
+            "-loglevel".to_owned(),
+            format!("level+{}", forced.unwrap_or(&level)),
+        ],
+    );
+}
+
+/// Whether a line FFmpeg logged is an actual error, as opposed to it just being chatty.
+/// Only known with `-loglevel level+..`, a filename with "error" in it is not one
+pub fn is_error(line: &str) -> bool {
+    matches!(level(line), Some("error" | "fatal" | "panic"))
+}
+
+/// FFmpeg's stderr, read on the side so it can be printed above the bar as it comes
+fn read_logs(ffmpeg: &mut Child) -> Option<Receiver<String>> {
+    let stderr = ffmpeg.stderr.take()?;
+    let (sender, receiver) = channel();
+
+    thread::spawn(move || {
+        for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+            if !line.trim().is_empty() && sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    Some(receiver)
+}
+
+fn format_size(bytes: u64) -> String {
+    match bytes {
+        b if b >= 1 << 30 => format!("{:.2} GiB", b as f64 / (1u64 << 30) as f64),
+        b if b >= 1 << 20 => format!("{:.1} MiB", b as f64 / (1u64 << 20) as f64),
+        b => format!("{:.0} KiB", b as f64 / 1024.0),
+    }
+}
+
+/// Progress bar for an FFmpeg spawned with a [progress::Channel]'s arguments and a piped stderr.
+/// Counts output frames if their total is known, seconds otherwise. Returns the error lines FFmpeg logged
+pub fn ffmpeg(
+    ffmpeg: &mut Child,
+    blocks: impl Read,
+    duration: Option<f64>,
+    total_frames: Option<usize>,
+) -> Result<Vec<String>, Error> {
     kdam::term::init(stderr().is_terminal());
 
-    let mut reader = BufReader::new(ffmpeg);
+    let logs = read_logs(ffmpeg);
+
+    let unit = if total_frames.is_some() {
+        " frame"
+    } else {
+        " second"
+    };
+
     let mut pb = RichProgress::new(
-        tqdm!(unit = " second".to_owned(), dynamic_ncols = true),
+        tqdm!(unit = unit.to_owned(), dynamic_ncols = true),
         vec![
             Column::Animation,
             Column::Percentage(1),
@@ -40,53 +113,143 @@ pub fn ffmpeg(ffmpeg: ChildStderr, duration: usize, fps: Option<i32>) -> Result<
         ],
     );
 
-    pb.pb.total = duration;
-
-    let read_byte = b'\r';
-
-    let progress_rx = Regex::new(r"time=(\d{2}):(\d{2}):(\d{2})\.\d{2}").unwrap();
+    pb.pb.total = total_frames.unwrap_or_else(|| duration.unwrap_or(0.0).round() as usize);
 
-    loop {
-        let prepend_text = "".to_owned();
-
-        thread::sleep(Duration::from_secs_f32(0.1));
-
-        let mut buf = vec![];
-        reader.read_until(read_byte, &mut buf)?;
-
-        if let Ok(line) = String::from_utf8(buf) {
-            let std_line = prepend_text + &line;
-
-            if std_line == "" {
-                pb.refresh()?;
-                eprintln!();
-                break;
-            }
+    let mut errors: Vec<String> = vec![];
+    let mut log = |pb: &mut RichProgress, line: String| -> Result<(), Error> {
+        if is_error(&line) {
+            errors.push(line.clone());
+            pb.write(line.red().to_string())
+        } else {
+            pb.write(line)
+        }
+    };
 
-            if let Some(x) = progress_rx.captures_iter(&std_line).next() {
-                let mut current =
-                    time_to_secs(&x).map_err(|_| new_error("couldn't parse current duration."))?;
+    let mut result: Result<(), Error> = Ok(());
+    progress::read_blocks(blocks, |block| {
+        if result.is_err() {
+            return;
+        }
 
-                if let Some(frames) = fps {
-                    current *= frames as usize;
-                    if pb.pb.total == duration {
-                        pb.pb.total *= frames as usize;
-                    }
+        if let Some(logs) = &logs {
+            for line in logs.try_iter() {
+                if let Err(e) = log(&mut pb, line) {
+                    result = Err(e);
+                    return;
                 }
+            }
+        }
 
-                pb.replace(9, Column::Text(format!("[red]{:.0} FPS", pb.pb.rate())));
+        let current = if total_frames.is_some() {
+            block
+                .get("frame")
+                .and_then(|frame| frame.parse::<usize>().ok())
+                .unwrap_or(0)
+        } else {
+            progress::parse_out_time(block).unwrap_or(0.0) as usize
+        };
+
+        let mut stats = format!(
+            "[red]{:.0} FPS",
+            block
+                .get("fps")
+                .and_then(|fps| fps.parse::<f64>().ok())
+                .unwrap_or(0.0)
+        );
+        if let Some(speed) = progress::parse_speed(block) {
+            stats.push_str(&format!(" • {speed:.2}x"));
+        }
+        if let Some(size) = block
+            .get("total_size")
+            .and_then(|size| size.parse::<u64>().ok())
+        {
+            stats.push_str(&format!(" • {}", format_size(size)));
+        }
+        pb.replace(9, Column::Text(stats));
 
-                if current >= pb.pb.total {
-                    pb.write(std_line.replace("\r", "").replace("\n", ""))?;
-                }
-                pb.update_to(current)?;
+        // the last frames can overshoot an estimated total
+        if current > pb.pb.total {
+            pb.pb.total = current;
+        }
+        result = pb.update_to(current).map(|_| ());
+    });
+    result?;
+    pb.refresh()?;
+    eprintln!();
+
+    // whatever FFmpeg said on its way out
+    if let Some(logs) = logs {
+        for line in logs.iter() {
+            if is_error(&line) {
+                eprintln!("{}", line.red());
+                errors.push(line);
             } else {
-                print!("{}", format!("{std_line}").red());
+                eprintln!("{line}");
             }
-        } else {
-            break;
         }
     }
 
-    Ok(())
+    Ok(errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_are_told_by_their_level() {
+        assert!(is_error("[error] Conversion failed!"));
+        assert!(is_error(
+            "[vost#0:0/libx264 @ 0x55d0c8] [error] Error while opening encoder"
+        ));
+        assert!(is_error(
+            "[in#0 @ 0x1] [fatal] Invalid data found when processing input"
+        ));
+        assert!(is_error("[panic] Assertion failed"));
+    }
+
+    #[test]
+    fn chatty_lines_are_not_errors() {
+        assert!(!is_error(
+            "[info] Output #0, mp4, to 'error invalid ~ Mango.mp4':"
+        ));
+        assert!(!is_error(
+            "[h264 @ 0x55d0] [warning] invalid NAL unit size, skipping"
+        ));
+        assert!(!is_error(
+            "  Stream #0:0: Video: h264 (error resilience: failed)"
+        ));
+        assert!(!is_error(""));
+
+        assert!(is_warning(
+            "[h264 @ 0x55d0] [warning] invalid NAL unit size, skipping"
+        ));
+        assert!(!is_warning("[error] Conversion failed!"));
+    }
+
+    #[test]
+    fn keeps_the_recipes_level() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+        let mut ff_args = args(&["-loglevel", "error", "-i", "-", "-v", "repeat+verbose"]);
+        print_levels(&mut ff_args, None);
+        assert_eq!(
+            ff_args,
+            args(&["-hide_banner", "-loglevel", "level+verbose", "-i", "-"])
+        );
+
+        let mut ff_args = args(&["-loglevel", "error", "-i", "-"]);
+        print_levels(&mut ff_args, Some("warning"));
+        assert_eq!(
+            ff_args,
+            args(&["-hide_banner", "-loglevel", "level+warning", "-i", "-"])
+        );
+
+        let mut ff_args = args(&["-i", "-"]);
+        print_levels(&mut ff_args, None);
+        assert_eq!(
+            ff_args,
+            args(&["-hide_banner", "-loglevel", "level+info", "-i", "-"])
+        );
+    }
 }
@@ -0,0 +1,181 @@
+use crate::cancel;
+use crate::recipe::Recipe;
+use crate::verb;
+use std::env;
+use std::io::Read;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Commands from the optional `[hooks]` section, ran through the shell around each render
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hook {
+    PreRender,
+    Success,
+    Failure,
+}
+
+impl Hook {
+    fn key(&self) -> &'static str {
+        match self {
+            Hook::PreRender => "pre render",
+            Hook::Success => "on success",
+            Hook::Failure => "on failure",
+        }
+    }
+}
+
+/// `[hooks] timeout:` in seconds, 60 by default and 0 to wait forever
+fn timeout(recipe: &Recipe) -> Option<Duration> {
+    let secs = match recipe.get_option("hooks", "timeout") {
+        Some(value) if !value.trim().is_empty() => {
+            value.trim().parse::<f64>().unwrap_or_else(|_| {
+                panic!("Failed parsing `[hooks] timeout:` {value:?} as a number of seconds")
+            })
+        }
+        _ => 60.0,
+    };
+
+    if secs <= 0.0 {
+        None
+    } else {
+        Some(Duration::from_secs_f64(secs))
+    }
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    use std::os::windows::process::CommandExt;
+    let mut cmd = Command::new("cmd");
+    // as is, Rust's quoting would escape the quotes around paths in a way cmd doesn't understand
+    cmd.arg("/C").raw_arg(command);
+    cmd
+}
+
+#[cfg(not(windows))]
+fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", command]);
+    cmd
+}
+
+/// A path as a single argument for `shell`, Windows paths can't contain `"`
+fn quote(path: &Path) -> String {
+    let path = path.display().to_string();
+    if cfg!(windows) {
+        format!("\"{path}\"")
+    } else {
+        format!("'{}'", path.replace('\'', r"'\''"))
+    }
+}
+
+/// Runs a hook if it's set, with %INPUT%, %OUTPUT% and %STATUS% filled in (also passed as SM_INPUT, SM_OUTPUT and SM_STATUS).
+/// The paths come already quoted, so they shouldn't be put in quotes in the recipe.
+/// Returns false if it failed or timed out, its output only shows up with --verbose
+pub fn run(recipe: &Recipe, hook: Hook, input: &Path, output: &Path, status: Option<i32>) -> bool {
+    let command = match recipe.get_option("hooks", hook.key()) {
+        Some(command) if !command.trim().is_empty() => command,
+        _ => return true,
+    };
+
+    let status = status.map(|code| code.to_string()).unwrap_or_default();
+    let command = command
+        .replace("%INPUT%", &quote(input))
+        .replace("%OUTPUT%", &quote(output))
+        .replace("%STATUS%", &status);
+
+    verb!("[hooks] {}: {command}", hook.key());
+
+    // in its own process group, so a timeout can kill whatever the shell started too
+    let mut child = match cancel::detach(&mut shell(&command))
+        .env("SM_INPUT", input)
+        .env("SM_OUTPUT", output)
+        .env("SM_STATUS", &status)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => {
+            eprintln!("Failed running `[hooks] {}:` hook: {e}", hook.key());
+            return false;
+        }
+    };
+
+    // read both pipes on the side so a chatty hook can't fill them up and hang
+    let mut stdout = child.stdout.take().expect("Failed capturing hook's stdout");
+    let mut stderr = child.stderr.take().expect("Failed capturing hook's stderr");
+    let stdout = thread::spawn(move || {
+        let mut text = String::new();
+        let _ = stdout.read_to_string(&mut text);
+        text
+    });
+    let stderr = thread::spawn(move || {
+        let mut text = String::new();
+        let _ = stderr.read_to_string(&mut text);
+        text
+    });
+
+    let timeout = timeout(recipe);
+    let start = Instant::now();
+    let exit = loop {
+        match child.try_wait() {
+            Ok(Some(exit)) => break Some(exit),
+            Ok(None) => {}
+            Err(e) => panic!("Failed waiting on `[hooks] {}:` hook: {e}", hook.key()),
+        }
+        if timeout.is_some_and(|timeout| start.elapsed() > timeout) {
+            cancel::kill_group(child.id());
+            let _ = child.wait();
+            break None;
+        }
+        thread::sleep(Duration::from_millis(100));
+    };
+
+    // a timed out hook's own children could still be holding the pipes open
+    if exit.is_some() {
+        for line in stdout
+            .join()
+            .unwrap_or_default()
+            .lines()
+            .chain(stderr.join().unwrap_or_default().lines())
+        {
+            verb!("[hooks] {}> {line}", hook.key());
+        }
+    }
+
+    match exit {
+        Some(exit) if exit.success() => true,
+        Some(exit) => {
+            eprintln!("`[hooks] {}:` hook failed ({exit})", hook.key());
+            false
+        }
+        None => {
+            eprintln!(
+                "`[hooks] {}:` hook timed out after {:.0}s",
+                hook.key(),
+                start.elapsed().as_secs_f64()
+            );
+            false
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quoted_paths_reach_the_shell_as_is() {
+        let path = Path::new("/tmp/it's $(echo nope) `x`; a & b.mp4");
+        let output = shell(&format!("printf %s {}", quote(path)))
+            .output()
+            .unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            path.display().to_string()
+        );
+    }
+}
@@ -4,25 +4,43 @@
 extern crate serde_derive;
 
 use recipe::Recipe;
-use render::vspipe_render;
+use render::render_queue;
 
 #[cfg(windows)]
 use winapi::um::{wincon::GetConsoleWindow, winuser::ShowWindow};
 
+mod audio;
+mod backend;
+mod cancel;
+mod chapters;
 mod cli;
 mod cmd;
+mod compare;
+mod cuts;
 mod ffpb;
+mod hooks;
 mod smgui;
 // mod ffpb2;
+mod metadata;
+mod metrics;
+mod notify;
 mod parse;
 mod portable;
+mod preview;
+mod progress;
+mod provenance;
 mod recipe;
 mod render;
+mod template;
+mod timecode;
+mod traceback;
 mod utils;
-//mod vapoursynth;
+#[cfg(feature = "vapoursynth")]
+mod vapoursynth;
 mod video;
+mod weighting;
 
-use crate::{cli::Arguments, cmd::SmCommand, video::Payload};
+use crate::{cli::Arguments, cmd::SmCommand, progress::ProgressFormat, video::Payload};
 use std::{env, sync::mpsc::channel};
 use utils::verbosity_init;
 
@@ -140,7 +158,13 @@ fn main() {
     };
 
     let return_recipe = args.return_recipe;
-    let progress = args.progress;
+    // json goes to stdout (or --progress-pipe) instead of the bar
+    let emitter = if args.progress_format == ProgressFormat::Json || args.progress_pipe.is_some() {
+        Some(progress::Emitter::new(args.progress_pipe.as_deref()))
+    } else {
+        None
+    };
+    let progress = args.progress && emitter.is_none();
 
     payloads = video::resolve_input(&mut args, &recipe);
     let commands: Vec<SmCommand> = cmd::build_commands(args, payloads, recipe);
@@ -152,6 +176,6 @@ fn main() {
             )
         }
     } else {
-        vspipe_render(commands, progress);
+        render_queue(commands, progress, emitter);
     }
 }
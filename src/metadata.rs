@@ -0,0 +1,181 @@
+use crate::recipe::Recipe;
+use crate::verb;
+use crate::video::Payload;
+use std::env;
+use std::fs::{self, File};
+use std::path::Path;
+use std::process::Command;
+
+/// What ffprobe-rs does not expose about the first video stream
+#[derive(Debug, Default)]
+struct VideoTags {
+    color_primaries: Option<String>,
+    color_transfer: Option<String>,
+    color_space: Option<String>,
+    color_range: Option<String>,
+    /// Degrees clockwise, the way the old `rotate` tag counts them
+    rotation: Option<i64>,
+}
+
+fn probe_video_tags(path: &Path) -> VideoTags {
+    let output = Command::new("ffprobe")
+        .args([
+            "-v",
+            "error",
+            "-select_streams",
+            "v:0",
+            "-show_entries",
+            "stream=color_primaries,color_transfer,color_space,color_range:stream_tags=rotate:stream_side_data=rotation",
+            "-of",
+            "json",
+        ])
+        .arg(path)
+        .output()
+        .expect("Failed running ffprobe to get video metadata");
+
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout)
+        .expect("Failed parsing ffprobe's video metadata as JSON");
+
+    let stream = &json["streams"][0];
+
+    // FFmpeg leaves these as "unknown" when untagged, which is not something to pass along
+    let tag = |key: &str| {
+        stream[key]
+            .as_str()
+            .filter(|value| !["unknown", "reserved", "unspecified"].contains(value))
+            .map(String::from)
+    };
+
+    // newer FFmpeg moved it to the display matrix side data, counter-clockwise
+    let rotation = stream["tags"]["rotate"]
+        .as_str()
+        .and_then(|rotate| rotate.parse::<i64>().ok())
+        .or_else(|| {
+            stream["side_data_list"].as_array().and_then(|side_data| {
+                side_data
+                    .iter()
+                    .find_map(|data| data["rotation"].as_i64())
+                    .map(|rotation| (-rotation).rem_euclid(360))
+            })
+        })
+        .filter(|rotation| *rotation != 0);
+
+    VideoTags {
+        color_primaries: tag("color_primaries"),
+        color_transfer: tag("color_transfer"),
+        color_space: tag("color_space"),
+        color_range: tag("color_range"),
+        rotation,
+    }
+}
+
+/// FFmpeg 6 stopped reading the `rotate` tag, rotation is set on the input's display matrix instead
+fn has_display_rotation(ffmpeg: &str) -> bool {
+    Command::new(ffmpeg)
+        .args(["-hide_banner", "-h", "full"])
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).contains("-display_rotation"))
+        .unwrap_or(false)
+}
+
+/// Options for FFmpeg's video input (`-i -`), tags it with the source's rotation for `[metadata] rotation: yes`
+/// when `ffmpeg` supports it. Autorotate is turned off so the frames are only tagged, not turned
+pub fn input_args(recipe: &Recipe, payload: &Payload, ffmpeg: &str) -> Vec<String> {
+    if !recipe.get_bool_or("metadata", "rotation", false) || !has_display_rotation(ffmpeg) {
+        return vec![];
+    }
+
+    match probe_video_tags(&payload.in_path).rotation {
+        Some(rotation) => vec![
+            "-autorotate".to_owned(),
+            "0".to_owned(),
+            "-display_rotation:v:0".to_owned(),
+            // counter-clockwise
+            (-rotation).rem_euclid(360).to_string(),
+        ],
+        None => vec![],
+    }
+}
+
+/// Arguments passing the source's metadata to the output, from the optional `[metadata]` section:
+/// * `copy: yes` copies the container's tags (title, comment, creation time..)
+/// * `creation time: yes` only copies the creation time
+/// * `rotation: yes` keeps phone videos upright, older FFmpeg builds get the `rotate` tag here
+///   while newer ones get it from [`input_args`]
+/// * `colors: yes` tags the output with the source's color primaries/transfer/matrix/range
+pub fn build_metadata_args(recipe: &Recipe, payload: &Payload, ffmpeg: &str) -> Vec<String> {
+    let mut args: Vec<String> = vec![];
+
+    if recipe.get_bool_or("metadata", "copy", false) {
+        args.append(&mut vec!["-map_metadata".to_owned(), "1".to_owned()]);
+    }
+
+    if recipe.get_bool_or("metadata", "creation time", false) {
+        let creation_time = payload
+            .probe
+            .format
+            .tags
+            .as_ref()
+            .and_then(|tags| tags.creation_time.clone());
+
+        match creation_time {
+            Some(creation_time) => args.append(&mut vec![
+                "-metadata".to_owned(),
+                format!("creation_time={creation_time}"),
+            ]),
+            None => verb!("{} has no creation time", payload.in_path.display()),
+        }
+    }
+
+    let rotation =
+        recipe.get_bool_or("metadata", "rotation", false) && !has_display_rotation(ffmpeg);
+    let colors = recipe.get_bool_or("metadata", "colors", false);
+
+    if !rotation && !colors {
+        return args;
+    }
+
+    let tags = probe_video_tags(&payload.in_path);
+    verb!("Source video metadata: {tags:?}");
+
+    if rotation {
+        if let Some(rotation) = tags.rotation {
+            args.append(&mut vec![
+                "-metadata:s:v:0".to_owned(),
+                format!("rotate={rotation}"),
+            ]);
+        }
+    }
+
+    if colors {
+        for (flag, value) in [
+            ("-color_primaries", tags.color_primaries),
+            ("-color_trc", tags.color_transfer),
+            ("-colorspace", tags.color_space),
+            ("-color_range", tags.color_range),
+        ] {
+            if let Some(value) = value {
+                args.append(&mut vec![flag.to_owned(), value]);
+            }
+        }
+    }
+
+    args
+}
+
+/// `[metadata] keep date modified: yes`, so renders sort next to their source
+pub fn copy_mtime(recipe: &Recipe, source: &Path, output: &Path) {
+    if !recipe.get_bool_or("metadata", "keep date modified", false) || !output.exists() {
+        return;
+    }
+
+    let modified = fs::metadata(source)
+        .and_then(|metadata| metadata.modified())
+        .unwrap_or_else(|e| panic!("Failed reading {}'s date modified: {e}", source.display()));
+
+    File::options()
+        .write(true)
+        .open(output)
+        .and_then(|file| file.set_modified(modified))
+        .unwrap_or_else(|e| panic!("Failed setting {}'s date modified: {e}", output.display()));
+}
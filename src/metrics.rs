@@ -0,0 +1,291 @@
+//! `[output] metrics:`, scores a finished output against what it was encoded from with FFmpeg's
+//! psnr / ssim (and libvmaf when FFmpeg was built with it), for comparing encoding presets.
+//! The reference is rendered again, so this takes about as long as the render itself
+
+use crate::backend;
+use crate::cmd::SmCommand;
+use crate::progress::{Emitter, Event};
+use crate::recipe::Recipe;
+use crate::verb;
+use regex::Regex;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Metric {
+    Psnr,
+    Ssim,
+    Vmaf,
+}
+
+impl Metric {
+    fn filter(&self) -> &'static str {
+        match self {
+            Metric::Psnr => "psnr",
+            Metric::Ssim => "ssim",
+            Metric::Vmaf => "libvmaf",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Psnr {
+    pub y: f64,
+    pub u: f64,
+    pub v: f64,
+    pub average: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Ssim {
+    pub y: f64,
+    pub u: f64,
+    pub v: f64,
+    pub all: f64,
+}
+
+/// What gets written to `<output>.metrics.json`
+#[derive(Debug, Clone, Serialize)]
+pub struct Scores {
+    pub output: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub psnr: Option<Psnr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ssim: Option<Ssim>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vmaf: Option<f64>,
+}
+
+/// `[output] metrics:`, `no` (default), `yes` for all of them (None) or a list like `psnr, ssim, vmaf`
+fn requested(recipe: &Recipe) -> Option<Vec<Metric>> {
+    let value = recipe
+        .get_option("output", "metrics")
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase();
+
+    if value.is_empty() || crate::NO.contains(&value.as_str()) {
+        return Some(vec![]);
+    }
+    if crate::YES.contains(&value.as_str()) {
+        return None;
+    }
+
+    let mut metrics = vec![];
+    for metric in value.split(',').map(str::trim).filter(|m| !m.is_empty()) {
+        let metric = match metric {
+            "psnr" => Metric::Psnr,
+            "ssim" => Metric::Ssim,
+            "vmaf" | "libvmaf" => Metric::Vmaf,
+            other => panic!(
+                "Unknown `[output] metrics:` {other:?}, expected no, yes or a list of psnr, ssim and vmaf"
+            ),
+        };
+        if !metrics.contains(&metric) {
+            metrics.push(metric);
+        }
+    }
+    Some(metrics)
+}
+
+/// Panics on a typo before anything gets rendered, instead of after the first video
+pub fn validate(recipe: &Recipe) {
+    requested(recipe);
+}
+
+fn wanted(cmd: &SmCommand) -> Vec<Metric> {
+    match requested(&cmd.recipe) {
+        None => {
+            let mut metrics = vec![Metric::Psnr, Metric::Ssim];
+            if has_vmaf(&cmd.process_path) {
+                metrics.push(Metric::Vmaf);
+            } else {
+                verb!("FFmpeg was built without libvmaf, only scoring PSNR and SSIM");
+            }
+            metrics
+        }
+        Some(mut metrics) => {
+            if metrics.contains(&Metric::Vmaf) && !has_vmaf(&cmd.process_path) {
+                eprintln!("FFmpeg was built without libvmaf, skipping VMAF");
+                metrics.retain(|metric| *metric != Metric::Vmaf);
+            }
+            metrics
+        }
+    }
+}
+
+fn has_vmaf(ffmpeg: &str) -> bool {
+    Command::new(ffmpeg)
+        .args(["-hide_banner", "-filters"])
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).contains(" libvmaf "))
+        .unwrap_or(false)
+}
+
+pub fn json_path(output: &Path) -> PathBuf {
+    output.with_extension("metrics.json")
+}
+
+/// Both inputs split once per metric, the output (distorted) always goes first like libvmaf wants
+fn graph(metrics: &[Metric]) -> String {
+    let pads = |prefix: &str| -> String {
+        (0..metrics.len())
+            .map(|i| format!("[{prefix}{i}]"))
+            .collect()
+    };
+    let mut graph = vec![
+        format!(
+            "[0:v]settb=AVTB,setpts=PTS-STARTPTS,split={}{}",
+            metrics.len(),
+            pads("out")
+        ),
+        format!(
+            "[ref]settb=AVTB,setpts=PTS-STARTPTS,split={}{}",
+            metrics.len(),
+            pads("ref")
+        ),
+    ];
+    for (i, metric) in metrics.iter().enumerate() {
+        graph.push(format!("[out{i}][ref{i}]{}", metric.filter()));
+    }
+    graph.join(";")
+}
+
+fn parse(output: &Path, log: &str) -> Scores {
+    let number = |value: &str| value.parse::<f64>().unwrap_or(f64::NAN);
+
+    let psnr = Regex::new(r"PSNR y:(\S+) u:(\S+) v:(\S+) average:(\S+)")
+        .unwrap()
+        .captures(log)
+        .map(|c| Psnr {
+            y: number(&c[1]),
+            u: number(&c[2]),
+            v: number(&c[3]),
+            average: number(&c[4]),
+        });
+    let ssim = Regex::new(r"SSIM Y:(\S+) \(\S+\) U:(\S+) \(\S+\) V:(\S+) \(\S+\) All:(\S+)")
+        .unwrap()
+        .captures(log)
+        .map(|c| Ssim {
+            y: number(&c[1]),
+            u: number(&c[2]),
+            v: number(&c[3]),
+            all: number(&c[4]),
+        });
+    let vmaf = Regex::new(r"VMAF score: (\S+)")
+        .unwrap()
+        .captures(log)
+        .map(|c| number(&c[1]));
+
+    Scores {
+        output: output.display().to_string(),
+        psnr,
+        ssim,
+        vmaf,
+    }
+}
+
+/// Scores `cmd`'s output if the recipe asks for it, printing them (an event in JSON mode) and writing them next to it.
+/// A failure is only reported, the render itself went fine
+pub fn report(cmd: &SmCommand, job: usize, emitter: &Option<Emitter>) {
+    let metrics = wanted(cmd);
+    if metrics.is_empty() {
+        return;
+    }
+    let output = &cmd.payload.out_path;
+    eprintln!("Scoring {}..", output.display());
+
+    let log = match backend::against_reference(cmd, &graph(&metrics)) {
+        Some(Ok(log)) => log,
+        Some(Err(e)) => {
+            eprintln!("Failed scoring {}: {e}", output.display());
+            return;
+        }
+        None => {
+            verb!("`[output] metrics:` only scores backends that write an output");
+            return;
+        }
+    };
+    let scores = parse(output, &log);
+
+    let mut summary = vec![];
+    if let Some(psnr) = &scores.psnr {
+        summary.push(format!("PSNR {:.2} dB", psnr.average));
+    }
+    if let Some(ssim) = &scores.ssim {
+        summary.push(format!("SSIM {:.4}", ssim.all));
+    }
+    if let Some(vmaf) = scores.vmaf {
+        summary.push(format!("VMAF {vmaf:.2}"));
+    }
+    if summary.is_empty() {
+        eprintln!("FFmpeg did not print any scores for {}", output.display());
+        verb!("{log}");
+        return;
+    }
+    eprintln!("{}: {}", output.display(), summary.join(", "));
+    if let Some(emitter) = emitter {
+        emitter.emit(&Event::Metrics {
+            job,
+            scores: scores.clone(),
+        });
+    }
+
+    let path = json_path(output);
+    let json = serde_json::to_string_pretty(&scores).expect("Failed serializing metrics to JSON");
+    // the render itself went fine, don't stop the queue over it
+    if let Err(e) = fs::write(&path, json) {
+        eprintln!("Failed writing metrics to {}: {e}", path.display());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_ffmpeg_summaries() {
+        let log = "\
+[Parsed_psnr_4 @ 0x1] PSNR y:44.521 u:48.012 v:47.930 average:45.412 min:40.1 max:inf
+[Parsed_ssim_5 @ 0x2] SSIM Y:0.991200 (20.560) U:0.993000 (21.549) V:0.992800 (21.426) All:0.991900 (20.912)
+[Parsed_libvmaf_6 @ 0x3] VMAF score: 96.412345";
+        let scores = parse(Path::new("out.mp4"), log);
+
+        assert_eq!(scores.psnr.unwrap().average, 45.412);
+        assert_eq!(scores.ssim.unwrap().all, 0.9919);
+        assert_eq!(scores.vmaf, Some(96.412345));
+    }
+
+    #[test]
+    fn splits_inputs_per_metric() {
+        assert_eq!(
+            graph(&[Metric::Psnr, Metric::Vmaf]),
+            "[0:v]settb=AVTB,setpts=PTS-STARTPTS,split=2[out0][out1];\
+             [ref]settb=AVTB,setpts=PTS-STARTPTS,split=2[ref0][ref1];\
+             [out0][ref0]psnr;[out1][ref1]libvmaf"
+        );
+    }
+    fn metrics(value: &str) -> Recipe {
+        let mut recipe = Recipe::new();
+        recipe.insert_value("output", "metrics".to_owned(), value.to_owned());
+        recipe
+    }
+
+    #[test]
+    fn reads_metrics_lists() {
+        assert_eq!(requested(&metrics("no")), Some(vec![]));
+        assert_eq!(requested(&metrics("yes")), None);
+        assert_eq!(
+            requested(&metrics("SSIM, psnr, ssim")),
+            Some(vec![Metric::Ssim, Metric::Psnr])
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown `[output] metrics:`")]
+    fn rejects_unknown_metrics() {
+        validate(&metrics("psnr, butteraugli"));
+    }
+}
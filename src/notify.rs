@@ -0,0 +1,174 @@
+use crate::recipe::Recipe;
+use crate::verb;
+use std::env;
+use std::f64::consts::PI;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+use which::which;
+
+/// A program that can play a .wav, `%FILE%` is replaced with the sound's path
+#[derive(Debug, Clone)]
+pub struct Player {
+    pub program: String,
+    pub args: Vec<String>,
+}
+
+impl Player {
+    fn new(program: &str, args: &[&str]) -> Player {
+        Player {
+            program: program.to_owned(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+        }
+    }
+
+    /// `[miscellaneous] ding player:` if set, otherwise the first one installed
+    pub fn from_recipe(recipe: &Recipe) -> Option<Player> {
+        if let Some(custom) = recipe
+            .get_option("miscellaneous", "ding player")
+            .filter(|custom| !custom.trim().is_empty())
+        {
+            let mut parts = custom.split_whitespace();
+            return Some(Player {
+                program: parts.next()?.to_owned(),
+                args: parts.map(String::from).collect(),
+            });
+        }
+
+        let candidates = if cfg!(target_os = "windows") {
+            vec![Player::new(
+                "powershell",
+                &[
+                    "-NoProfile",
+                    "-Command",
+                    "(New-Object Media.SoundPlayer '%FILE%').PlaySync()",
+                ],
+            )]
+        } else if cfg!(target_os = "macos") {
+            vec![Player::new("afplay", &["%FILE%"])]
+        } else {
+            vec![
+                Player::new("paplay", &["%FILE%"]),
+                Player::new("pw-play", &["%FILE%"]),
+                Player::new("aplay", &["-q", "%FILE%"]),
+            ]
+        };
+
+        candidates
+            .into_iter()
+            .chain([Player::new(
+                "ffplay",
+                &["-nodisp", "-autoexit", "-loglevel", "quiet", "%FILE%"],
+            )])
+            .find(|player| which(&player.program).is_ok())
+    }
+
+    pub fn play(&self, sound: &Path) -> bool {
+        let sound = sound.display().to_string();
+
+        Command::new(&self.program)
+            .args(self.args.iter().map(|arg| arg.replace("%FILE%", &sound)))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    }
+}
+
+/// The ding, two decaying sines as a 16-bit mono .wav
+fn ding_wav() -> Vec<u8> {
+    let rate: u32 = 44100;
+    let samples: Vec<i16> = (0..rate * 7 / 10)
+        .map(|i| {
+            let t = i as f64 / rate as f64;
+            let tone = (2.0 * PI * 880.0 * t).sin() * 0.6 + (2.0 * PI * 1320.0 * t).sin() * 0.3;
+            (tone * (-6.0 * t).exp() * 0.5 * i16::MAX as f64) as i16
+        })
+        .collect();
+
+    let data_len = samples.len() as u32 * 2;
+    let mut wav: Vec<u8> = vec![];
+    wav.extend(b"RIFF");
+    wav.extend((36 + data_len).to_le_bytes());
+    wav.extend(b"WAVEfmt ");
+    wav.extend(16u32.to_le_bytes()); // fmt chunk size
+    wav.extend(1u16.to_le_bytes()); // PCM
+    wav.extend(1u16.to_le_bytes()); // mono
+    wav.extend(rate.to_le_bytes());
+    wav.extend((rate * 2).to_le_bytes()); // byte rate
+    wav.extend(2u16.to_le_bytes()); // block align
+    wav.extend(16u16.to_le_bytes()); // bits per sample
+    wav.extend(b"data");
+    wav.extend(data_len.to_le_bytes());
+    for sample in samples {
+        wav.extend(sample.to_le_bytes());
+    }
+    wav
+}
+
+fn write_ding() -> Option<PathBuf> {
+    let path = env::temp_dir().join("smoothie-ding.wav");
+    if !path.exists() {
+        fs::write(&path, ding_wav()).ok()?;
+    }
+    Some(path)
+}
+
+pub fn play_ding(recipe: &Recipe) {
+    let played = match (Player::from_recipe(recipe), write_ding()) {
+        (Some(player), Some(sound)) => {
+            verb!("Playing ding with {}", player.program);
+            player.play(&sound)
+        }
+        _ => false,
+    };
+
+    if !played {
+        // terminal bell
+        eprint!("\x07");
+        let _ = std::io::stderr().flush();
+    }
+}
+
+/// freedesktop notification through notify-send, silently does nothing if it's not there
+fn desktop_notification(summary: &str, body: &str) {
+    if !cfg!(target_os = "linux") || which("notify-send").is_err() {
+        return;
+    }
+
+    let _ = Command::new("notify-send")
+        .args(["--app-name=Smoothie", summary, body])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+}
+
+/// Called once the whole queue is done: prints a summary, and with `[miscellaneous] play ding:`
+/// plays the ding and sends a desktop notification (`[miscellaneous] notification:` to only do the latter)
+pub fn queue_finished(recipe: &Recipe, succeeded: usize, failed: usize, elapsed: Duration) {
+    let summary = if failed == 0 {
+        "Smoothie finished rendering".to_owned()
+    } else {
+        "Smoothie finished rendering, with errors".to_owned()
+    };
+    let body = format!(
+        "{succeeded} rendered, {failed} failed in {}",
+        crate::template::format_duration(elapsed.as_secs_f64())
+    );
+
+    // stderr, stdout might be carrying --progress-format json
+    eprintln!("{body}");
+
+    let ding = recipe.get_bool_or("miscellaneous", "play ding", false);
+
+    if recipe.get_bool_or("miscellaneous", "notification", ding) {
+        desktop_notification(&summary, &body);
+    }
+    if ding {
+        play_ding(recipe);
+    }
+}
@@ -0,0 +1,195 @@
+//! Single frames rendered for the GUI's preview pane, through the recipe as it is being edited.
+//! Goes through vspipe's `--start` / `--end` like --peek, or FFmpeg's filters when there's no vspipe
+
+use crate::backend::{self, ffmpeg};
+use crate::cli::Arguments;
+use crate::cmd;
+use crate::cuts::{self, CutType};
+use crate::recipe::Recipe;
+use crate::video;
+use ffprobe::FfProbe;
+use num_rational::Ratio;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+/// A source frame next to what the recipe makes of it, both as PNGs
+pub struct Preview {
+    pub source: Vec<u8>,
+    pub smoothie: Vec<u8>,
+    /// Which frame of the output `smoothie` is
+    pub output_frame: u64,
+}
+
+/// How many frames the source has, what the scrubber goes up to
+pub fn frame_count(probe: &FfProbe) -> Option<u64> {
+    let stream = probe
+        .streams
+        .iter()
+        .find(|stream| stream.codec_type == Some("video".to_owned()))?;
+
+    if let Some(frames) = stream.nb_frames.as_ref().and_then(|n| n.parse().ok()) {
+        return Some(frames);
+    }
+    let duration = probe.format.duration.as_ref()?.parse::<f64>().ok()?;
+    Some((duration * video::source_fps(probe)?) as u64)
+}
+
+/// The output frame showing the same moment as the source's `frame`, after `[runtime] timecodes`' cuts
+fn output_frame(recipe: &Recipe, probe: &FfProbe, frame: u64) -> Result<u64, String> {
+    let ratio = video::FpsChain::from_probe(recipe, probe)
+        .ok_or("Failed getting the output's frame rate")?
+        .frame_ratio();
+
+    let frame = match cuts::from_recipe(recipe) {
+        Some(timecodes) => {
+            let fps = video::source_fps(probe).ok_or("Failed getting the source's frame rate")?;
+            let duration = probe
+                .format
+                .duration
+                .as_ref()
+                .and_then(|duration| duration.parse::<f64>().ok())
+                .unwrap_or(0.0);
+            cuts::cut_frame(
+                &timecodes,
+                CutType::from_recipe(recipe),
+                fps,
+                duration,
+                frame,
+            )
+            .ok_or_else(|| format!("Frame {frame} is not in any of `[runtime] timecodes:`' cuts"))?
+        }
+        None => frame,
+    };
+
+    Ok((Ratio::from_integer(frame as i64) * ratio)
+        .round()
+        .to_integer() as u64)
+}
+
+/// Renders `frame` of `input` in the background, `done` is called once it's sent
+pub fn spawn(
+    recipe: Recipe,
+    args: Arguments,
+    input: PathBuf,
+    frame: u64,
+    done: impl FnOnce() + Send + 'static,
+) -> Receiver<Result<Preview, String>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = sender.send(render(&recipe, &args, &input, frame));
+        done();
+    });
+    receiver
+}
+
+fn render(recipe: &Recipe, args: &Arguments, input: &Path, frame: u64) -> Result<Preview, String> {
+    let ffmpeg_path = backend::ffmpeg_path(recipe)?;
+    let probe = video::probe_video(&input.to_path_buf())
+        .ok_or_else(|| format!("Failed probing {}", input.display()))?;
+    let fps = video::source_fps(&probe).ok_or("Failed getting the source's frame rate")?;
+    let output_frame = output_frame(recipe, &probe, frame)?;
+
+    // -ss before -i still decodes up to the exact frame when re-encoding
+    let source = run(
+        Command::new(&ffmpeg_path)
+            .args(["-hide_banner", "-loglevel", "error", "-ss"])
+            .arg(format!("{}", frame as f64 / fps))
+            .arg("-i")
+            .arg(input)
+            .args(png_args()),
+        "FFmpeg",
+    )?;
+
+    let smoothie = match cmd::find_vspipe(args) {
+        Some(vspipe) => {
+            let vpy = cmd::find_vpy(args).ok_or("jamba.vpy not found")?;
+            let mut vspipe = Command::new(vspipe)
+                .args(["--container", "y4m", "-"])
+                .arg(vpy)
+                .args(["--arg".to_owned(), cmd::recipe_arg(recipe)])
+                .arg("--arg")
+                .arg(format!("input_video={}", input.display()))
+                .args(["--start", &output_frame.to_string()])
+                .args(["--end", &output_frame.to_string()])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .map_err(|e| format!("Failed spawning vspipe: {e}"))?;
+            let y4m = vspipe.stdout.take().expect("Failed piping out of vspipe");
+            // drained alongside FFmpeg, vspipe would block on a full stderr pipe otherwise
+            let mut stderr = vspipe
+                .stderr
+                .take()
+                .expect("Failed capturing vspipe's stderr");
+            let log = thread::spawn(move || {
+                let mut log = String::new();
+                let _ = stderr.read_to_string(&mut log);
+                log
+            });
+
+            let png = run(
+                Command::new(&ffmpeg_path)
+                    .args(["-hide_banner", "-loglevel", "error", "-i", "-"])
+                    .args(png_args())
+                    .stdin(y4m),
+                "FFmpeg",
+            );
+
+            let log = log.join().unwrap_or_default();
+            let status = vspipe.wait().map_err(|e| e.to_string())?;
+            if !status.success() {
+                return Err(format!("vspipe failed:\n{}", log.trim()));
+            }
+            png?
+        }
+        None => {
+            let needs = ffmpeg::needs_vapoursynth(recipe);
+            if !needs.is_empty() {
+                return Err(format!(
+                    "vspipe not found, and FFmpeg alone can't preview {}",
+                    needs.join(", ")
+                ));
+            }
+            run(
+                Command::new(&ffmpeg_path)
+                    .args(["-hide_banner", "-loglevel", "error", "-i"])
+                    .arg(input)
+                    .arg("-vf")
+                    .arg(ffmpeg::filters(recipe, &probe, Some(output_frame)).join(","))
+                    .args(png_args()),
+                "FFmpeg",
+            )?
+        }
+    };
+
+    Ok(Preview {
+        source,
+        smoothie,
+        output_frame,
+    })
+}
+
+/// A single frame as a PNG on stdout
+fn png_args() -> [&'static str; 7] {
+    ["-frames:v", "1", "-f", "image2pipe", "-c:v", "png", "-"]
+}
+
+/// Runs `command` and returns its stdout, or what it printed if it failed
+fn run(command: &mut Command, name: &str) -> Result<Vec<u8>, String> {
+    let output = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|e| format!("Failed spawning {name}: {e}"))?;
+
+    if !output.status.success() || output.stdout.is_empty() {
+        return Err(format!(
+            "{name} failed:\n{}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(output.stdout)
+}
@@ -0,0 +1,277 @@
+use crate::metrics::Scores;
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::{stdout, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::TcpListener;
+use std::path::Path;
+use std::process::Child;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum ProgressFormat {
+    /// kdam progress bar in the terminal
+    Bar,
+    /// Newline-delimited JSON events, for wrappers
+    Json,
+}
+
+/// One line of `--progress-format json`
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Started {
+        job: usize,
+        jobs: usize,
+        input: String,
+        output: String,
+    },
+    Progress {
+        job: usize,
+        frame: Option<u64>,
+        fps: Option<f64>,
+        speed: Option<f64>,
+        /// Seconds of output written so far
+        out_time: Option<f64>,
+        percent: Option<f64>,
+        /// Seconds left, from the current speed
+        eta: Option<f64>,
+    },
+    Warning {
+        job: usize,
+        message: String,
+    },
+    Finished {
+        job: usize,
+        output: String,
+        elapsed: f64,
+    },
+    Failed {
+        job: usize,
+        input: String,
+        /// hook, script, missing_plugin, encoder or vapoursynth
+        kind: &'static str,
+        status: Option<i32>,
+        message: String,
+    },
+    /// `[output] metrics:` scores of a finished output
+    Metrics {
+        job: usize,
+        scores: Scores,
+    },
+    /// Stopped with Ctrl+C
+    Cancelled {
+        job: usize,
+        input: String,
+    },
+}
+
+/// Where events go, stdout or --progress-pipe (a file or named pipe), shared between threads
+#[derive(Clone)]
+pub struct Emitter {
+    out: Arc<Mutex<Box<dyn Write + Send>>>,
+}
+
+impl Emitter {
+    pub fn new(pipe: Option<&Path>) -> Emitter {
+        let out: Box<dyn Write + Send> = match pipe {
+            Some(pipe) => Box::new(
+                OpenOptions::new()
+                    .append(true)
+                    .create(true)
+                    .open(pipe)
+                    .unwrap_or_else(|e| {
+                        panic!("Failed opening --progress-pipe {}: {e}", pipe.display())
+                    }),
+            ),
+            None => Box::new(stdout()),
+        };
+
+        Emitter {
+            out: Arc::new(Mutex::new(out)),
+        }
+    }
+
+    pub fn emit(&self, event: &Event) {
+        let line = serde_json::to_string(event).expect("Failed serializing progress event");
+        let mut out = self.out.lock().expect("Progress output lock was poisoned");
+        // a wrapper closing its end early should not take the render down with it
+        let _ = writeln!(out, "{line}");
+        let _ = out.flush();
+    }
+}
+
+/// Where FFmpeg writes its `-progress` blocks: its stdout, or a localhost socket
+/// when stdout is already busy feeding the preview window
+pub enum Channel {
+    Stdout,
+    Socket(TcpListener),
+}
+
+impl Channel {
+    pub fn new(previewing: bool) -> Channel {
+        if !previewing {
+            return Channel::Stdout;
+        }
+
+        let listener = TcpListener::bind(("127.0.0.1", 0))
+            .unwrap_or_else(|e| panic!("Failed opening a socket for FFmpeg's progress: {e}"));
+        listener
+            .set_nonblocking(true)
+            .expect("Failed setting up the progress socket");
+        Channel::Socket(listener)
+    }
+
+    pub fn uses_stdout(&self) -> bool {
+        matches!(self, Channel::Stdout)
+    }
+
+    /// Arguments that make FFmpeg write `key=value` progress blocks to this channel
+    pub fn ffmpeg_args(&self) -> Vec<String> {
+        let url = match self {
+            Channel::Stdout => "pipe:1".to_owned(),
+            Channel::Socket(listener) => format!(
+                "tcp://{}",
+                listener
+                    .local_addr()
+                    .expect("Failed getting the progress socket's address")
+            ),
+        };
+
+        vec!["-progress".to_owned(), url, "-nostats".to_owned()]
+    }
+
+    /// Whatever FFmpeg's progress can be read from, None if it exited before it connected
+    pub fn reader(self, ffmpeg: &mut Child) -> Option<Box<dyn Read + Send>> {
+        match self {
+            Channel::Stdout => ffmpeg
+                .stdout
+                .take()
+                .map(|stdout| Box::new(stdout) as Box<dyn Read + Send>),
+            Channel::Socket(listener) => loop {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        stream
+                            .set_nonblocking(false)
+                            .expect("Failed setting up the progress socket");
+                        break Some(Box::new(stream));
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                        if !matches!(ffmpeg.try_wait(), Ok(None)) {
+                            break None;
+                        }
+                        thread::sleep(Duration::from_millis(50));
+                    }
+                    Err(e) => panic!("Failed reading FFmpeg's progress: {e}"),
+                }
+            },
+        }
+    }
+}
+
+/// Seconds of output written so far
+pub fn parse_out_time(block: &HashMap<String, String>) -> Option<f64> {
+    // out_time_ms is in microseconds too, an old FFmpeg mistake
+    block
+        .get("out_time_us")
+        .or_else(|| block.get("out_time_ms"))
+        .and_then(|us| us.parse::<f64>().ok())
+        .map(|us| us / 1_000_000.0)
+        .filter(|secs| *secs >= 0.0)
+}
+
+/// `speed=1.5x`, N/A until FFmpeg has an idea
+pub fn parse_speed(block: &HashMap<String, String>) -> Option<f64> {
+    block
+        .get("speed")
+        .and_then(|speed| speed.trim().trim_end_matches('x').parse::<f64>().ok())
+}
+
+/// Reads FFmpeg's `-progress` output, calling `on_block` once per block (everything up to `progress=continue` / `progress=end`).
+/// The `progress` key is kept in the block so the last one can be told apart
+pub fn read_blocks(reader: impl Read, mut on_block: impl FnMut(&HashMap<String, String>)) {
+    let mut block: HashMap<String, String> = HashMap::new();
+
+    for line in BufReader::new(reader).lines().map_while(Result::ok) {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        block.insert(key.trim().to_owned(), value.trim().to_owned());
+
+        if key.trim() == "progress" {
+            on_block(&block);
+            block.clear();
+        }
+    }
+}
+
+/// Turns one `-progress` block (everything up to `progress=continue`) into an event
+pub fn progress_event(job: usize, block: &HashMap<String, String>, duration: Option<f64>) -> Event {
+    let out_time = parse_out_time(block);
+    let speed = parse_speed(block);
+
+    let percent = match (out_time, duration) {
+        (Some(out_time), Some(duration)) if duration > 0.0 => {
+            Some((out_time / duration * 100.0).min(100.0))
+        }
+        _ => None,
+    };
+    let eta = match (out_time, duration, speed) {
+        (Some(out_time), Some(duration), Some(speed)) if speed > 0.0 => {
+            Some(((duration - out_time) / speed).max(0.0))
+        }
+        _ => None,
+    };
+
+    Event::Progress {
+        job,
+        frame: block.get("frame").and_then(|frame| frame.parse().ok()),
+        fps: block.get("fps").and_then(|fps| fps.parse().ok()),
+        speed,
+        out_time,
+        percent,
+        eta,
+    }
+}
+
+/// Reads FFmpeg's progress and the warnings and errors it logs on stderr, until it exits.
+/// FFmpeg has to be spawned with [crate::ffpb::print_levels] for them to be told apart from the rest.
+/// `duration` is the expected output length in seconds, for percent and eta. Returns the error lines FFmpeg logged
+pub fn watch(
+    ffmpeg: &mut Child,
+    blocks: impl Read,
+    emitter: &Emitter,
+    job: usize,
+    duration: Option<f64>,
+) -> Vec<String> {
+    let stderr = ffmpeg
+        .stderr
+        .take()
+        .expect("Failed capturing FFmpeg's stderr");
+    let warnings = {
+        let emitter = emitter.clone();
+        thread::spawn(move || {
+            let mut errors = vec![];
+            for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                let error = crate::ffpb::is_error(&line);
+                if error {
+                    errors.push(line.trim().to_owned());
+                }
+                if error || crate::ffpb::is_warning(&line) {
+                    emitter.emit(&Event::Warning {
+                        job,
+                        message: line.trim().to_owned(),
+                    });
+                }
+            }
+            errors
+        })
+    };
+
+    read_blocks(blocks, |block| {
+        emitter.emit(&progress_event(job, block, duration));
+    });
+
+    warnings.join().unwrap_or_default()
+}
@@ -0,0 +1,265 @@
+use crate::recipe::Recipe;
+use crate::verb;
+use crate::video::{Payload, Timecodes};
+use indexmap::IndexMap;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Everything needed to tell how an output was made, written as JSON
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Provenance {
+    pub smoothie: String,
+    pub source: String,
+    pub sha256: String,
+    pub timecodes: Option<Vec<Timecodes>>,
+    pub enc_args: String,
+    pub recipe: IndexMap<String, IndexMap<String, String>>,
+}
+
+/// `[output] provenance:` tells where the record goes, `no` (default), `metadata`, `sidecar` or `both`
+fn provenance_mode(recipe: &Recipe) -> (bool, bool) {
+    match recipe
+        .get_option("output", "provenance")
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase()
+        .as_str()
+    {
+        "metadata" | "embed" => (true, false),
+        "sidecar" => (false, true),
+        "both" => (true, true),
+        value if value.is_empty() || crate::NO.contains(&value) => (false, false),
+        value if crate::YES.contains(&value) => (true, false),
+        other => panic!(
+            "Unknown `[output] provenance:` {other:?}, expected no, metadata, sidecar or both"
+        ),
+    }
+}
+
+pub fn wants_sidecar(recipe: &Recipe) -> bool {
+    provenance_mode(recipe).1
+}
+
+fn hash_file(path: &Path) -> String {
+    let mut file = File::open(path)
+        .unwrap_or_else(|e| panic!("Failed opening {} to hash it: {e}", path.display()));
+
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 1024 * 1024];
+    loop {
+        let read = file
+            .read(&mut buffer)
+            .unwrap_or_else(|e| panic!("Failed reading {} to hash it: {e}", path.display()));
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Returns None if `[output] provenance:` is off, hashing big sources takes a moment
+pub fn build(recipe: &Recipe, payload: &Payload, enc_args: &[String]) -> Option<Provenance> {
+    let (metadata, sidecar) = provenance_mode(recipe);
+    if !metadata && !sidecar {
+        return None;
+    }
+
+    verb!("Hashing {} for provenance", payload.in_path.display());
+
+    Some(Provenance {
+        smoothie: env!("CARGO_PKG_VERSION").to_owned(),
+        source: payload
+            .in_path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        sha256: hash_file(&payload.in_path),
+        timecodes: payload.timecodes.clone(),
+        enc_args: enc_args.join(" "),
+        recipe: recipe.data.clone(),
+    })
+}
+
+/// Stored in the container's `comment` tag, the one tag every container keeps
+pub fn metadata_args(recipe: &Recipe, provenance: &Provenance) -> Vec<String> {
+    if !provenance_mode(recipe).0 {
+        return vec![];
+    }
+
+    let json = serde_json::to_string(provenance).expect("Failed serializing provenance to JSON");
+
+    vec!["-metadata".to_owned(), format!("comment={json}")]
+}
+
+/// `clip.mp4` => `clip.smoothie.json`
+pub fn sidecar_path(output: &Path) -> PathBuf {
+    output.with_extension("smoothie.json")
+}
+
+pub fn write_sidecar(provenance: &Provenance, output: &Path) {
+    let path = sidecar_path(output);
+    let json =
+        serde_json::to_string_pretty(provenance).expect("Failed serializing provenance to JSON");
+
+    fs::write(&path, json)
+        .unwrap_or_else(|e| panic!("Failed writing provenance to {}: {e}", path.display()));
+}
+
+/// Looks for a sidecar first, then in the file's `comment` tag
+pub fn read(output: &Path) -> Option<Provenance> {
+    let sidecar = sidecar_path(output);
+    if sidecar.exists() {
+        let content = fs::read_to_string(&sidecar)
+            .unwrap_or_else(|e| panic!("Failed reading {}: {e}", sidecar.display()));
+        return Some(serde_json::from_str(&content).unwrap_or_else(|e| {
+            panic!("Failed parsing {} as provenance: {e}", sidecar.display())
+        }));
+    }
+
+    let output = Command::new("ffprobe")
+        .args([
+            "-v",
+            "error",
+            "-show_entries",
+            "format_tags=comment",
+            "-of",
+            "json",
+        ])
+        .arg(output)
+        .output()
+        .expect("Failed running ffprobe to read provenance");
+
+    from_comment_tag(&output.stdout)
+}
+
+/// ffprobe's `-show_entries format_tags=comment -of json`
+fn from_comment_tag(ffprobe_json: &[u8]) -> Option<Provenance> {
+    let json: serde_json::Value =
+        serde_json::from_slice(ffprobe_json).expect("Failed parsing ffprobe's output as JSON");
+
+    json["format"]["tags"]["comment"]
+        .as_str()
+        .and_then(|comment| serde_json::from_str(comment).ok())
+}
+
+/// Turns a provenance record back into a recipe that can be passed to --recipe
+pub fn to_ini(provenance: &Provenance) -> String {
+    let mut ini = format!(
+        "# made with smoothie {} from {} (sha256 {})\n# enc args: {}\n",
+        provenance.smoothie, provenance.source, provenance.sha256, provenance.enc_args
+    );
+
+    if let Some(timecodes) = &provenance.timecodes {
+        ini.push_str(&format!("# cuts: {}\n", crate::cuts::to_runtime(timecodes)));
+    }
+
+    // [runtime] only makes sense for the source it was made from
+    for (section, keys) in &provenance.recipe {
+        if section == "runtime" {
+            continue;
+        }
+        ini.push_str(&format!("\n[{section}]\n"));
+        for (key, value) in keys {
+            ini.push_str(&format!("{key}: {value}\n"));
+        }
+    }
+    ini
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recipe::parse_recipe;
+
+    fn recipe() -> Recipe {
+        let mut recipe = Recipe::new();
+        for (section, key, value) in [
+            ("output", "provenance", "both"),
+            ("frame blending", "enabled", "yes"),
+            ("frame blending", "weighting", "[1, 2, 1]; wizardry = yes"),
+            ("runtime", "timecodes", "0:01-0:05"),
+        ] {
+            recipe.insert_value(section, key.to_owned(), value.to_owned());
+        }
+        recipe
+    }
+
+    fn provenance(recipe: &Recipe) -> Provenance {
+        Provenance {
+            smoothie: "0.0.0".to_owned(),
+            source: "clip.mp4".to_owned(),
+            sha256: "ab".repeat(32),
+            timecodes: Some(vec![Timecodes {
+                start: "0:01".to_owned(),
+                fin: "0:05".to_owned(),
+            }]),
+            enc_args: "-c:v libx264 -crf 18".to_owned(),
+            recipe: recipe.data.clone(),
+        }
+    }
+
+    /// What `sm inspect` prints, parsed back like --recipe would
+    fn ini_recipe(provenance: &Provenance) -> Recipe {
+        let mut parsed = Recipe::new();
+        parse_recipe(
+            PathBuf::new(),
+            Some(to_ini(provenance)),
+            &mut parsed,
+            &mut None,
+            false,
+        );
+        parsed
+    }
+
+    fn assert_same_recipe(parsed: &Recipe) {
+        assert_eq!(parsed.get("output", "provenance"), "both");
+        assert_eq!(parsed.get("frame blending", "enabled"), "yes");
+        assert_eq!(
+            parsed.get("frame blending", "weighting"),
+            "[1, 2, 1]; wizardry = yes"
+        );
+        // only meant for the source it was made from
+        assert!(parsed.get_option("runtime", "timecodes").is_none());
+    }
+
+    #[test]
+    fn comment_tag_roundtrip() {
+        let recipe = recipe();
+        let args = metadata_args(&recipe, &provenance(&recipe));
+        assert_eq!(args[0], "-metadata");
+        let comment = args[1].strip_prefix("comment=").unwrap();
+
+        let ffprobe = serde_json::json!({ "format": { "tags": { "comment": comment } } });
+        let read = from_comment_tag(ffprobe.to_string().as_bytes()).unwrap();
+
+        assert_eq!(read.sha256, "ab".repeat(32));
+        assert_eq!(read.enc_args, "-c:v libx264 -crf 18");
+        assert_same_recipe(&ini_recipe(&read));
+    }
+
+    #[test]
+    fn sidecar_roundtrip() {
+        let recipe = recipe();
+        let output =
+            env::temp_dir().join(format!("smoothie-provenance-{}.mp4", std::process::id()));
+        write_sidecar(&provenance(&recipe), &output);
+
+        let read = read(&output);
+        let _ = fs::remove_file(sidecar_path(&output));
+        let read = read.unwrap();
+
+        assert_eq!(read.source, "clip.mp4");
+        assert!(to_ini(&read).contains("# cuts: 0:01-0:05\n"));
+        assert_same_recipe(&ini_recipe(&read));
+    }
+}
@@ -88,6 +88,16 @@ impl Recipe {
             None => None,
         }
     }
+
+    /// Same as get_bool, but for keys that may not be in defaults.ini (e.g [audio])
+    pub fn get_bool_or(&self, section: &str, key: &str, default: bool) -> bool {
+        match self.get_option(section, key) {
+            Some(value) if !value.trim().is_empty() => {
+                crate::YES.contains(&value.trim().to_lowercase().as_str())
+            }
+            _ => default,
+        }
+    }
     pub fn _get_mut(&mut self, section: &str, key: &str) -> String {
         match self.data.get_mut(section) {
             Some(section) => match section.get_mut(key) {
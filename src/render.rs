@@ -1,100 +1,236 @@
+use crate::backend::Report;
+use crate::cancel::{self, Job, JobState, Stop};
+use crate::chapters;
 use crate::cmd::SmCommand;
-use crate::verb;
-use std::env;
-use std::process::{Command, Stdio};
-
-pub fn vspipe_render(commands: Vec<SmCommand>, mut progress: bool) {
-    for cmd in commands {
-        let previewing: bool =
-            cmd.recipe.get_bool("preview window", "enabled") && cmd.ffplay_args.is_some();
-
-        if previewing && progress {
-            progress = false;
-            println!(
-                "Progress bar is currently not compatible with preview window, disabling progress"
+use crate::hooks::{self, Hook};
+use crate::metadata;
+use crate::metrics;
+use crate::notify;
+use crate::progress::{Emitter, Event};
+use crate::provenance;
+use crate::recipe::Recipe;
+use crate::traceback;
+use crate::video;
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+fn render_failed(
+    cmd: &SmCommand,
+    job: usize,
+    emitter: &Option<Emitter>,
+    failure: &traceback::Failure,
+    status: Option<i32>,
+) {
+    let (in_path, out_path) = (&cmd.payload.in_path, &cmd.payload.out_path);
+
+    let partial = video::partial_path(out_path);
+    if partial.exists() {
+        let _ = fs::remove_file(&partial);
+    }
+    hooks::run(&cmd.recipe, Hook::Failure, in_path, out_path, status);
+
+    eprintln!("Failed rendering {}", in_path.display());
+    traceback::report(failure);
+    if let Some(emitter) = emitter {
+        emitter.emit(&Event::Failed {
+            job,
+            input: in_path.display().to_string(),
+            kind: failure.kind(),
+            status,
+            message: failure.to_string(),
+        });
+    }
+}
+
+/// An aborted render's partial is removed, unless `[output] keep partial:` where it's left with its `.partial` name
+fn discard_partial(recipe: &Recipe, partial: &Path) {
+    if partial.exists() && !recipe.get_bool_or("output", "keep partial", false) {
+        let _ = fs::remove_file(partial);
+    }
+}
+
+/// The queue was cancelled, what's left is written down. Pending renders already gave back
+/// the names they reserved when their commands were dropped
+fn stopped(queue: &[Job]) -> ! {
+    let done = queue
+        .iter()
+        .filter(|job| job.state == JobState::Done)
+        .count();
+    let last = queue
+        .iter()
+        .rev()
+        .find(|job| job.state != JobState::Pending)
+        .unwrap_or(&queue[0]);
+    let state = cancel::write_state(queue, &last.output);
+
+    eprintln!(
+        "Stopped after {done} of {} renders, queue state written to {}",
+        queue.len(),
+        state.display()
+    );
+    std::process::exit(130);
+}
+
+/// Renders a single job of the queue with its backend, reporting how it went
+fn render_job(
+    cmd: &SmCommand,
+    job: usize,
+    jobs: usize,
+    progress: bool,
+    emitter: &Option<Emitter>,
+) -> JobState {
+    let (in_path, out_path) = (cmd.payload.in_path.clone(), cmd.payload.out_path.clone());
+    // FFmpeg writes to a partial file that only takes the final name once it's done
+    let partial = video::partial_path(&out_path);
+
+    if let Some(emitter) = emitter {
+        emitter.emit(&Event::Started {
+            job,
+            jobs,
+            input: in_path.display().to_string(),
+            output: out_path.display().to_string(),
+        });
+    }
+
+    if !hooks::run(&cmd.recipe, Hook::PreRender, &in_path, &out_path, None) {
+        if partial.exists() {
+            let _ = fs::remove_file(&partial);
+        }
+        let message = format!(
+            "`[hooks] pre render:` hook failed, not rendering {}",
+            in_path.display()
+        );
+        eprintln!("{message}");
+        if let Some(emitter) = emitter {
+            emitter.emit(&Event::Failed {
+                job,
+                input: in_path.display().to_string(),
+                kind: "hook",
+                status: None,
+                message,
+            });
+        }
+        return JobState::Failed;
+    }
+
+    let report = match emitter {
+        Some(emitter) => Report::Json { emitter, job },
+        None if progress => Report::Bar,
+        None => Report::Off,
+    };
+    let mut backend = cmd.backend.create();
+    let job_start = Instant::now();
+
+    if let Err(failure) = backend.spawn(cmd, &report) {
+        render_failed(cmd, job, emitter, &failure, None);
+        return JobState::Failed;
+    }
+    let watchdog = backend.cancel();
+    backend.progress(cmd, &report);
+    let (status, failure) = backend.wait();
+    watchdog.finish();
+
+    if cancel::requested() == Stop::Abort {
+        discard_partial(&cmd.recipe, &partial);
+        if let Some(emitter) = emitter {
+            emitter.emit(&Event::Cancelled {
+                job,
+                input: in_path.display().to_string(),
+            });
+        }
+        return JobState::Cancelled;
+    }
+
+    if let Some(failure) = failure {
+        render_failed(cmd, job, emitter, &failure, status);
+        return JobState::Failed;
+    }
+    if partial.exists() {
+        fs::rename(&partial, &out_path).unwrap_or_else(|e| {
+            panic!(
+                "Failed renaming {} to {}: {e}",
+                partial.display(),
+                out_path.display()
             )
+        });
+    }
+
+    if let Some(provenance) = &cmd.provenance {
+        if provenance::wants_sidecar(&cmd.recipe) {
+            provenance::write_sidecar(provenance, &out_path);
         }
+    }
+    metadata::copy_mtime(&cmd.recipe, &in_path, &out_path);
+    metrics::report(cmd, job, emitter);
 
-        verb!("FF args: {}", cmd.ff_args.join(" "));
+    hooks::run(&cmd.recipe, Hook::Success, &in_path, &out_path, status);
 
-        if previewing {
-            verb!(
-                "FFplay args: {}",
-                &cmd.ffplay_args.clone().unwrap().join(" ")
-            );
+    if let Some(emitter) = emitter {
+        emitter.emit(&Event::Finished {
+            job,
+            output: out_path.display().to_string(),
+            elapsed: job_start.elapsed().as_secs_f64(),
+        });
+    }
+    JobState::Done
+}
+
+/// Renders each command with its backend, one after the other
+pub fn render_queue(commands: Vec<SmCommand>, progress: bool, emitter: Option<Emitter>) {
+    let recipe = match commands.first() {
+        Some(cmd) => cmd.recipe.clone(),
+        None => return,
+    };
+    let start = Instant::now();
+    // a failed render does not stop the queue, it's reported once everything's done
+    let mut queue: Vec<Job> = commands
+        .iter()
+        .map(|cmd| Job {
+            input: cmd.payload.in_path.clone(),
+            output: cmd.payload.out_path.clone(),
+            state: JobState::Pending,
+        })
+        .collect();
+
+    let jobs = commands.len();
+    cancel::install();
+
+    let mut commands = commands.into_iter().enumerate();
+    for (job, cmd) in commands.by_ref() {
+        if cancel::requested() != Stop::Continue {
+            chapters::remove(&cmd.chapters);
+            break;
         }
+        queue[job].state = render_job(&cmd, job, jobs, progress, &emitter);
+        chapters::remove(&cmd.chapters);
 
-        let vs = Command::new(cmd.vs_path)
-            .args(cmd.vs_args)
-            .stdout(Stdio::piped())
-            .spawn()
-            .expect("Failed in spawning FFmpeg child");
-
-        let pipe = vs.stdout.expect("Failed piping out of VSPipe");
-
-        let mut ffmpeg = Command::new(cmd.ff_path)
-            .args(cmd.ff_args)
-            .stdin(pipe)
-            .stdout(if previewing {
-                Stdio::piped()
-            } else {
-                Stdio::null()
-            })
-            .stderr(if progress {
-                Stdio::piped()
-            } else {
-                Stdio::inherit()
-            })
-            .spawn()
-            .expect("Failed in spawning FFmpeg child");
-
-        if progress {
-            let stderr = ffmpeg
-                .stderr
-                .expect("failed to capture ffmpeg standard error.");
-
-            let fps: i32 = if cmd.recipe.get_bool("frame blending", "enabled") {
-                cmd.recipe
-                    .get("frame blending", "fps")
-                    .parse::<i32>()
-                    .unwrap()
-            } else {
-                for stream in cmd.payload.probe.streams {
-                    if stream.codec_type == Some("video".to_owned()) {
-                        stream.avg_frame_rate.parse::<i32>().unwrap();
-                        break;
-                    } else {
-                        continue;
-                    }
-                }
-                panic!("Failed finding a probe video stream");
-            };
-
-            let duration = cmd
-                .payload
-                .probe
-                .format
-                .duration
-                .expect("Failed getting probe duration")
-                .parse::<f32>()
-                .unwrap()
-                .round() as usize;
-
-            let _a = crate::ffpb::ffmpeg(stderr, duration, Some(fps));
-        } else {
-            if previewing {
-                let ffplay_pipe = ffmpeg.stdout.take().expect("Failed piping out of FFmpeg");
-                let ffplay = Command::new(cmd.ffplay_path.unwrap())
-                    .args(cmd.ffplay_args.unwrap())
-                    .stdin(ffplay_pipe)
-                    .spawn()
-                    .expect("Failed in spawning ffplay child");
-                ffplay.wait_with_output().unwrap();
-            }
-            let status = ffmpeg.wait_with_output().unwrap().status;
-            if !status.success() {
-                panic!("ffmpeg / vapoursynth did not return sucessfully\n\nIF YOU ARE TAKING A SCREENSHOT WHEN ASKING FOR SUPPORT MAKE SURE TO INCLUDE THE TERMINAL's WHICH IS WHERE THE ERROR IS EXPLAINED");
-            }
+        if queue[job].state == JobState::Cancelled {
+            break;
         }
     }
+    // the ones that never ran wrote their chapters too, and give back their reservation when dropped
+    for (_, cmd) in commands {
+        chapters::remove(&cmd.chapters);
+    }
+
+    if cancel::requested() != Stop::Continue {
+        stopped(&queue);
+    }
+
+    let failed: Vec<String> = queue
+        .iter()
+        .filter(|job| job.state == JobState::Failed)
+        .map(|job| job.input.display().to_string())
+        .collect();
+    let succeeded = queue
+        .iter()
+        .filter(|job| job.state == JobState::Done)
+        .count();
+
+    notify::queue_finished(&recipe, succeeded, failed.len(), start.elapsed());
+
+    if !failed.is_empty() {
+        panic!("ffmpeg / vapoursynth did not return sucessfully for:\n{}\n\nIF YOU ARE TAKING A SCREENSHOT WHEN ASKING FOR SUPPORT MAKE SURE TO INCLUDE THE TERMINAL's WHICH IS WHERE THE ERROR IS EXPLAINED", failed.join("\n"));
+    }
 }
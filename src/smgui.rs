@@ -5,7 +5,7 @@ use crate::{
 use std::{
     fs::File, io::Write,
     path::PathBuf,
-    sync::mpsc::Sender // used to retrieve SmCommands
+    sync::mpsc::{Receiver, Sender, TryRecvError} // Sender is used to retrieve SmCommands
 };
 use copypasta::{ClipboardContext, ClipboardProvider};
 use eframe::egui;
@@ -37,6 +37,18 @@ struct SmApp {
     sender: Sender<(Recipe, Arguments, WinHWND)>,
     make_new_recipe: bool,
     new_recipe_filename: String,
+    preview: PreviewPane,
+}
+
+/// A source frame next to the same one through the unsaved recipe, see crate::preview
+#[derive(Default)]
+struct PreviewPane {
+    input: Option<PathBuf>,
+    frames: u64,
+    frame: u64,
+    job: Option<Receiver<Result<crate::preview::Preview, String>>>,
+    textures: Option<(egui::TextureHandle, egui::TextureHandle, u64)>,
+    error: Option<String>,
 }
 
 pub const WINDOW_NAME: &str = "smoothie-app";
@@ -128,6 +140,7 @@ pub fn sm_gui<'gui>(
                     sender,
                     make_new_recipe: false,
                     new_recipe_filename: String::new(),
+                    preview: PreviewPane::default(),
             }
         ))
     }),
@@ -249,6 +262,33 @@ impl eframe::App for SmApp {
                     }
                 }
 
+                if ui
+                    .button("preview")
+                    .on_hover_text_at_pointer("look at a frame through this recipe without rendering")
+                    .clicked()
+                {
+                    let input = rfd::FileDialog::new()
+                        .add_filter("Video file", crate::VIDEO_EXTENSIONS)
+                        .set_title("Select a video to preview")
+                        .pick_file();
+
+                    if let Some(input) = input {
+                        let frames = crate::video::probe_video(&input)
+                            .as_ref()
+                            .and_then(crate::preview::frame_count);
+                        match frames {
+                            Some(frames) => {
+                                self.preview = PreviewPane {
+                                    input: Some(input),
+                                    frames,
+                                    ..PreviewPane::default()
+                                };
+                                self.preview.render(ctx, &self.recipe, &self.args);
+                            }
+                            None => eprintln!("Failed getting the frame count of {}", input.display()),
+                        }
+                    }
+                }
 
                 // declare buttons and immediately handle if it's clicked OR if keyboard shortcut is "consumed" (pressed)
                 if ui
@@ -327,6 +367,16 @@ impl eframe::App for SmApp {
                 });
              //});
             egui::menu::bar(ui, |_| {}); // <br>
+
+            // shown under `file format`, rendered with a made up 1080p 240fps video
+            let format_preview = crate::template::preview(
+                &self.recipe,
+                &binding.file_stem().unwrap_or_default().to_string_lossy(),
+            );
+
+            // plotted under `weighting`, blending a made up 240fps video (or what it's interpolated to)
+            let weights_preview = weights_preview(&self.recipe);
+
             egui::ScrollArea::vertical().show(ui, |ui| {
                 for cat in &mut self.metadata.keys() {
                     let mut first_run: bool = true;
@@ -411,6 +461,47 @@ impl eframe::App for SmApp {
                                             );
                                         }
                                     });
+                                if cat == "frame blending" && key == "weighting" {
+                                    match &weights_preview {
+                                        Some(Ok(weights)) => {
+                                            // `[..]; wizardry = yes` keeps its option while being edited
+                                            let (list, options) = match value.split_once(';') {
+                                                Some((list, options)) => (list.to_owned(), format!(";{options}")),
+                                                None => (value.clone(), String::new()),
+                                            };
+                                            let wizardry = options
+                                                .split(';')
+                                                .filter_map(|option| option.split_once('='))
+                                                .any(|(param, on)| {
+                                                    param.trim() == "wizardry" && crate::YES.contains(&on.trim())
+                                                });
+                                            match crate::weighting::parse_list(list.trim()) {
+                                                Some(mut list) => {
+                                                    if weights_editor(ui, &mut list, weights, wizardry) {
+                                                        *value = crate::weighting::format_list(&list) + &options;
+                                                    }
+                                                }
+                                                None => {
+                                                    weights_plot(ui, weights);
+                                                    if ui
+                                                        .button("customize")
+                                                        .on_hover_text("edit these weights as a custom list")
+                                                        .clicked()
+                                                    {
+                                                        // tallest bar as 1, easier to read than the normalized weights
+                                                        let max = weights.iter().cloned().fold(f64::EPSILON, f64::max);
+                                                        let list: Vec<f64> = weights.iter().map(|w| w / max).collect();
+                                                        *value = crate::weighting::format_list(&list);
+                                                    }
+                                                }
+                                            }
+                                        }
+                                        Some(Err(e)) => {
+                                            ui.colored_label(ui.visuals().error_fg_color, e);
+                                        }
+                                        None => {}
+                                    }
+                                }
                             }
                             "bool" => {
                                 let mut bool: bool = crate::YES.contains(&value.as_str());
@@ -479,6 +570,9 @@ impl eframe::App for SmApp {
                                     ui.label(key.to_owned() + ":");
                                     ui.add(egui::TextEdit::singleline(&mut *value));
                                 });
+                                if cat == "output" && key == "file format" {
+                                    ui.weak(format!("preview: {format_preview}"));
+                                }
                             }
                             _ => {
                                 ui.label(key.to_owned() + "- TODO");
@@ -772,6 +866,9 @@ impl eframe::App for SmApp {
                     self.metadata = metadata;
                 }
             }
+            if self.preview.input.is_some() {
+                self.preview.show(ctx, &self.recipe, &self.args);
+            }
             if self.show_confirmation_dialog {
                 if format!("{:?}", self.recipe) != self.recipe_saved {
                     egui::Window::new(
@@ -818,6 +915,243 @@ impl eframe::App for SmApp {
     }
 }
 
+impl PreviewPane {
+    fn render(&mut self, ctx: &egui::Context, recipe: &Recipe, args: &Arguments) {
+        let Some(input) = self.input.clone() else {
+            return;
+        };
+        let ctx = ctx.clone();
+        self.error = None;
+        self.job = Some(crate::preview::spawn(
+            recipe.clone(),
+            args.clone(),
+            input,
+            self.frame,
+            move || ctx.request_repaint(),
+        ));
+    }
+
+    fn show(&mut self, ctx: &egui::Context, recipe: &Recipe, args: &Arguments) {
+        if let Some(job) = &self.job {
+            match job.try_recv() {
+                Ok(Ok(preview)) => {
+                    let load = |name: &str, png: &[u8]| -> Result<egui::TextureHandle, String> {
+                        let image = image::load_from_memory(png)
+                            .map_err(|e| e.to_string())?
+                            .to_rgba8();
+                        let size = [image.width() as usize, image.height() as usize];
+                        let image = egui::ColorImage::from_rgba_unmultiplied(size, image.as_raw());
+                        Ok(ctx.load_texture(name, image, egui::TextureOptions::default()))
+                    };
+                    match (load("source", &preview.source), load("smoothie", &preview.smoothie)) {
+                        (Ok(source), Ok(smoothie)) => {
+                            self.textures = Some((source, smoothie, preview.output_frame))
+                        }
+                        (Err(e), _) | (_, Err(e)) => self.error = Some(e),
+                    }
+                    self.job = None;
+                }
+                Ok(Err(e)) => {
+                    self.error = Some(e);
+                    self.job = None;
+                }
+                Err(TryRecvError::Disconnected) => {
+                    self.error = Some("The preview crashed, see the console".to_owned());
+                    self.job = None;
+                }
+                Err(TryRecvError::Empty) => {}
+            }
+        }
+
+        let mut open = true;
+        let name = self
+            .input
+            .as_ref()
+            .and_then(|input| input.file_name())
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        egui::Window::new(format!("preview: {name}"))
+            .open(&mut open)
+            .default_width(640.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    let slider = ui.add(
+                        egui::Slider::new(&mut self.frame, 0..=self.frames.saturating_sub(1))
+                            .text("frame"),
+                    );
+                    let button = ui.add_enabled(self.job.is_none(), egui::Button::new("render"));
+                    // dragging would queue a render per frame it passes
+                    if button.clicked()
+                        || slider.drag_stopped()
+                        || (slider.changed() && !slider.dragged())
+                    {
+                        self.render(ctx, recipe, args);
+                    }
+                    if self.job.is_some() {
+                        ui.spinner();
+                    }
+                });
+                if let Some(error) = &self.error {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                }
+                if let Some((source, smoothie, output_frame)) = &self.textures {
+                    let width = (ui.available_width() - ui.spacing().item_spacing.x) / 2.0;
+                    ui.horizontal(|ui| {
+                        for (label, texture) in [
+                            ("source".to_owned(), source),
+                            (format!("smoothie (frame {output_frame})"), smoothie),
+                        ] {
+                            ui.vertical(|ui| {
+                                ui.label(label);
+                                let size = texture.size_vec2();
+                                ui.image((texture.id(), size * (width / size.x)));
+                            });
+                        }
+                    });
+                }
+            });
+
+        if !open {
+            *self = PreviewPane::default();
+        }
+    }
+}
+
+/// Weights `[frame blending]` would use on a 240fps video, after the rest of the recipe's fps chain
+fn weights_preview(recipe: &Recipe) -> Option<Result<Vec<f64>, String>> {
+    let input_fps = crate::video::FpsChain::new(recipe, num_rational::Ratio::from_integer(240))
+        .map(|chain| *chain.blending.numer() as f64 / *chain.blending.denom() as f64)
+        .unwrap_or(240.0);
+    crate::weighting::from_recipe(recipe, input_fps)
+}
+
+/// Bar chart of blending weights, one bar per frame
+fn weights_plot(ui: &mut egui::Ui, weights: &[f64]) {
+    let signed = weights.iter().any(|w| *w < 0.0);
+    bars(ui, weights, tallest(weights), signed, egui::Sense::hover()).on_hover_text(format!(
+        "{} frames: {}",
+        weights.len(),
+        crate::weighting::format(weights)
+    ));
+}
+
+/// A custom `[..]` weighting as bars that can be dragged, true if one was
+fn weights_editor(
+    ui: &mut egui::Ui,
+    list: &mut Vec<f64>,
+    weights: &[f64],
+    wizardry: bool,
+) -> bool {
+    // the tallest bar is the top of the chart, weights are relative anyways.
+    // it's kept for the whole drag, else pulling the tallest bar down would rescale everything under the cursor
+    let id = ui.id().with("weights drag scale");
+    let held: Option<f64> = ui.memory(|mem| mem.data.get_temp(id));
+    let scale = held.unwrap_or_else(|| tallest(list));
+
+    // with wizardry bars can be dragged under the baseline
+    let signed = wizardry || list.iter().any(|w| *w < 0.0);
+
+    let response = bars(ui, list, scale, signed, egui::Sense::click_and_drag()).on_hover_text(format!(
+        "stretched to {} frames: {}",
+        weights.len(),
+        crate::weighting::format(weights)
+    ));
+    if response.drag_started() {
+        ui.memory_mut(|mem| mem.data.insert_temp(id, scale));
+    } else if response.drag_stopped() {
+        ui.memory_mut(|mem| mem.data.remove::<f64>(id));
+    }
+    let mut changed = false;
+
+    if response.clicked() || response.dragged() {
+        if let Some(pos) = response.interact_pointer_pos() {
+            let rect = response.rect;
+            let i = ((pos.x - rect.left()) / rect.width() * list.len() as f32)
+                .clamp(0.0, list.len() as f32 - 1.0) as usize;
+            let baseline = baseline(rect, signed);
+            let lowest = if wizardry { -1.0 } else { 0.0 };
+            let height = ((baseline - pos.y) / (baseline - rect.top())).clamp(lowest, 1.0) as f64;
+            let weight = (height * scale * 100.0).round() / 100.0;
+
+            let total: f64 = list.iter().sum::<f64>() - list[i] + weight;
+            if list[i] != weight && total > 0.0 {
+                list[i] = weight;
+                changed = true;
+            }
+        }
+    }
+
+    ui.horizontal(|ui| {
+        if ui.small_button("+").on_hover_text("add a bar").clicked() {
+            list.push(*list.last().unwrap());
+            changed = true;
+        }
+        if ui
+            .small_button("-")
+            .on_hover_text("remove the last bar")
+            .clicked()
+            && list.len() > 1
+        {
+            list.pop();
+            changed = true;
+        }
+        ui.weak("drag the bars to reshape the curve");
+    });
+    changed
+}
+
+fn tallest(values: &[f64]) -> f64 {
+    values.iter().fold(f64::EPSILON, |max, w| max.max(w.abs()))
+}
+
+/// Where the bars start, centered when negative weights (wizardry) go under it
+fn baseline(rect: egui::Rect, signed: bool) -> f32 {
+    if signed {
+        rect.center().y
+    } else {
+        rect.bottom()
+    }
+}
+
+/// Paints one bar per value, `max` being the top of the chart
+fn bars(
+    ui: &mut egui::Ui,
+    values: &[f64],
+    max: f64,
+    signed: bool,
+    sense: egui::Sense,
+) -> egui::Response {
+    let (rect, response) =
+        ui.allocate_exact_size(egui::vec2(ui.available_width().min(300.0), 60.0), sense);
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 2.0, ui.visuals().extreme_bg_color);
+
+    let max = max as f32;
+    let baseline = baseline(rect, signed);
+    let height = baseline - rect.top();
+    let width = rect.width() / values.len() as f32;
+    let fill = if response.hovered() && sense.senses_drag() {
+        ui.visuals().widgets.hovered.bg_fill
+    } else {
+        ui.visuals().selection.bg_fill
+    };
+
+    for (i, value) in values.iter().enumerate() {
+        let x = rect.left() + i as f32 * width;
+        let y = baseline - *value as f32 / max * height;
+        painter.rect_filled(
+            egui::Rect::from_two_pos(
+                egui::pos2(x + width * 0.1, y),
+                egui::pos2(x + width * 0.9, baseline),
+            ),
+            0.0,
+            fill,
+        );
+    }
+    response
+}
+
 /// Preview hovering files:
 fn preview_files_being_dropped(ctx: &egui::Context) {
     use egui::*;
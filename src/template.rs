@@ -0,0 +1,450 @@
+use crate::recipe::Recipe;
+use chrono::Local;
+use ffprobe::FfProbe;
+use std::path::PathBuf;
+
+/// What the source video brings to `[output] file format:`
+#[derive(Debug, Clone, Default)]
+pub struct Source {
+    pub basename: String,
+    pub width: Option<i64>,
+    pub height: Option<i64>,
+    pub fps: Option<f64>,
+    pub duration: Option<f64>,
+    pub codec: Option<String>,
+}
+
+impl Source {
+    pub fn from_probe(basename: &str, probe: &FfProbe) -> Source {
+        let video = probe
+            .streams
+            .iter()
+            .find(|stream| stream.codec_type == Some("video".to_owned()));
+
+        Source {
+            basename: basename.to_owned(),
+            width: video.and_then(|stream| stream.width),
+            height: video.and_then(|stream| stream.height),
+            fps: crate::video::source_fps(probe),
+            duration: probe
+                .format
+                .duration
+                .as_ref()
+                .and_then(|duration| duration.parse::<f64>().ok()),
+            codec: video.and_then(|stream| stream.codec_name.clone()),
+        }
+    }
+
+    /// Stand-in used by the GUI to preview the file format
+    pub fn example() -> Source {
+        Source {
+            basename: "video".to_owned(),
+            width: Some(1920),
+            height: Some(1080),
+            fps: Some(240.0),
+            duration: Some(65.0),
+            codec: Some("h264".to_owned()),
+        }
+    }
+}
+
+// (section, key, variable)
+const RECIPE_VARIABLES: &[(&str, &str, &str)] = &[
+    ("interpolation", "fps", "INTERP_FPS"),
+    ("interpolation", "speed", "SPEED"),
+    ("interpolation", "tuning", "TUNING"),
+    ("interpolation", "algorithm", "ALGORITHM"),
+    ("frame blending", "fps", "OUTPUT_FPS"),
+    ("frame blending", "intensity", "BLUR_AMOUNT"),
+    ("frame blending", "weighting", "WEIGHTING"),
+    ("flowblur", "intensity", "FLOWBLUR_AMOUNT"),
+    ("miscellaneous", "dedup threshold", "DEDUP"),
+    ("pre-interp", "factor", "FACTOR"),
+];
+
+#[derive(Debug)]
+enum Token {
+    Literal(String),
+    Variable(String),
+    /// `{...}`, dropped if any of its variables are empty
+    Group(Vec<Token>),
+}
+
+fn tokenize(chars: &mut std::str::Chars, nested: bool) -> Result<Vec<Token>, String> {
+    let mut tokens: Vec<Token> = vec![];
+    let mut literal = String::new();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '%' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('%') => break,
+                        Some(c) => name.push(c),
+                        None => return Err(format!("Unclosed variable `%{name}`")),
+                    }
+                }
+                if name.is_empty() {
+                    // %% is a literal %
+                    literal.push('%');
+                    continue;
+                }
+                if !literal.is_empty() {
+                    tokens.push(Token::Literal(std::mem::take(&mut literal)));
+                }
+                tokens.push(Token::Variable(name.trim().to_uppercase()));
+            }
+            '{' => {
+                if !literal.is_empty() {
+                    tokens.push(Token::Literal(std::mem::take(&mut literal)));
+                }
+                tokens.push(Token::Group(tokenize(chars, true)?));
+            }
+            '}' if nested => {
+                if !literal.is_empty() {
+                    tokens.push(Token::Literal(literal));
+                }
+                return Ok(tokens);
+            }
+            '}' => return Err("Found a `}` without a matching `{`".to_owned()),
+            c => literal.push(c),
+        }
+    }
+
+    if nested {
+        return Err("Found a `{` without a matching `}`".to_owned());
+    }
+    if !literal.is_empty() {
+        tokens.push(Token::Literal(literal));
+    }
+    Ok(tokens)
+}
+
+/// Characters Windows does not allow in filenames, `/` and `\` are kept in literals for subfolders
+fn sanitize(text: &str, keep_separators: bool) -> String {
+    text.chars()
+        .map(|c| match c {
+            '/' | '\\' if keep_separators => c,
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            _ => c,
+        })
+        .collect()
+}
+
+/// `65.0` => `1m05s`
+pub fn format_duration(secs: f64) -> String {
+    let secs = secs.round() as u64;
+    if secs >= 3600 {
+        format!("{}h{:02}m{:02}s", secs / 3600, secs / 60 % 60, secs % 60)
+    } else if secs >= 60 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{secs}s")
+    }
+}
+
+struct Context<'a> {
+    recipe: &'a Recipe,
+    recipe_name: &'a str,
+    source: &'a Source,
+    fruit: &'a str,
+}
+
+impl Context<'_> {
+    /// Empty means the variable has no value (or its section is disabled), unknown variables are an error
+    fn resolve(&self, name: &str) -> Result<String, String> {
+        let now = Local::now();
+
+        let value = match name {
+            "FILENAME" => self.source.basename.clone(),
+            "FRUIT" | "FRUITS" => self.fruit.to_owned(),
+            "RECIPE" => self.recipe_name.to_owned(),
+            "DATE" => now.format("%Y-%m-%d").to_string(),
+            "TIME" => now.format("%H-%M-%S").to_string(),
+            "YEAR" => now.format("%Y").to_string(),
+            "MONTH" => now.format("%m").to_string(),
+            "DAY" => now.format("%d").to_string(),
+            "HOUR" => now.format("%H").to_string(),
+            "MINUTE" => now.format("%M").to_string(),
+            "SECOND" => now.format("%S").to_string(),
+            "RES" | "RESOLUTION" => match (self.source.width, self.source.height) {
+                (Some(width), Some(height)) => format!("{width}x{height}"),
+                _ => String::new(),
+            },
+            "WIDTH" => self.source.width.map(|w| w.to_string()).unwrap_or_default(),
+            "HEIGHT" => self
+                .source
+                .height
+                .map(|h| h.to_string())
+                .unwrap_or_default(),
+            "SOURCE_FPS" => self
+                .source
+                .fps
+                .map(|fps| {
+                    format!("{:.2}", fps)
+                        .trim_end_matches('0')
+                        .trim_end_matches('.')
+                        .to_owned()
+                })
+                .unwrap_or_default(),
+            "DURATION" => self
+                .source
+                .duration
+                .map(format_duration)
+                .unwrap_or_default(),
+            "CODEC" => self.source.codec.clone().unwrap_or_default(),
+            _ => {
+                let (section, key, _) = RECIPE_VARIABLES
+                    .iter()
+                    .find(|(_, _, var)| *var == name)
+                    .ok_or_else(|| {
+                        format!("Unknown variable `%{name}%` in `[output] file format:`")
+                    })?;
+
+                let disabled = self
+                    .recipe
+                    .get_option(section, "enabled")
+                    .is_some_and(|enabled| {
+                        !crate::YES.contains(&enabled.trim().to_lowercase().as_str())
+                    });
+
+                if disabled {
+                    String::new()
+                } else {
+                    let mut value = self.recipe.get_option(section, key).unwrap_or_default();
+                    // truncate weighting var if too long
+                    if *key == "weighting" && value.len() > 15 {
+                        value = format!("{}..", value.chars().take(15).collect::<String>());
+                    }
+                    value
+                }
+            }
+        };
+
+        Ok(sanitize(value.trim(), false))
+    }
+
+    /// Returns the text and whether every variable in it had a value
+    fn render(&self, tokens: &[Token]) -> Result<(String, bool), String> {
+        let mut text = String::new();
+        let mut complete = true;
+
+        for token in tokens {
+            match token {
+                Token::Literal(literal) => text.push_str(&sanitize(literal, true)),
+                Token::Variable(name) => {
+                    let value = self.resolve(name)?;
+                    complete &= !value.is_empty();
+                    text.push_str(&value);
+                }
+                Token::Group(group) => {
+                    let (group_text, group_complete) = self.render(group)?;
+                    if group_complete {
+                        text.push_str(&group_text);
+                    }
+                }
+            }
+        }
+        Ok((text, complete))
+    }
+}
+
+/// Renders a file format, e.g `%FILENAME%{ ~ %BLUR_AMOUNT% blur}` into a path relative to the output folder.
+/// Literals keep their case, variable names don't care, `/` makes subfolders
+pub fn render(
+    template: &str,
+    recipe: &Recipe,
+    recipe_name: &str,
+    source: &Source,
+    fruit: &str,
+) -> Result<PathBuf, String> {
+    let tokens = tokenize(&mut template.chars(), false)?;
+
+    let (text, _) = Context {
+        recipe,
+        recipe_name,
+        source,
+        fruit,
+    }
+    .render(&tokens)?;
+
+    let path: PathBuf = text
+        .split(['/', '\\'])
+        .map(|component| component.trim().trim_end_matches('.'))
+        .filter(|component| !component.is_empty())
+        .collect();
+
+    if path.as_os_str().is_empty() {
+        return Err(format!(
+            "`[output] file format:` {template:?} gives an empty filename"
+        ));
+    }
+    Ok(path)
+}
+
+/// What the GUI shows under `file format`
+pub fn preview(recipe: &Recipe, recipe_name: &str) -> String {
+    let template = recipe
+        .get_option("output", "file format")
+        .unwrap_or_default();
+    let container = recipe
+        .get_option("output", "container")
+        .unwrap_or_default()
+        .trim()
+        .replace('.', "");
+
+    match render(&template, recipe, recipe_name, &Source::example(), "Mango") {
+        Ok(path) => format!(
+            "{}.{}",
+            path.display(),
+            if container.is_empty() {
+                "MP4"
+            } else {
+                &container
+            }
+        ),
+        Err(e) => e,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn recipe() -> Recipe {
+        let mut recipe = Recipe::new();
+        for (section, key, value) in [
+            ("frame blending", "enabled", "yes"),
+            ("frame blending", "fps", "60"),
+            ("frame blending", "intensity", "1.5"),
+            ("interpolation", "enabled", "no"),
+            ("interpolation", "fps", "1920"),
+        ] {
+            recipe.insert_value(section, key.to_owned(), value.to_owned());
+        }
+        recipe
+    }
+
+    fn render_with(template: &str, source: &Source) -> Result<PathBuf, String> {
+        render(template, &recipe(), "recipe.ini", source, "Mango")
+    }
+
+    fn render_example(template: &str) -> Result<PathBuf, String> {
+        render_with(template, &Source::example())
+    }
+
+    #[test]
+    fn renders_variables() {
+        assert_eq!(
+            render_example("%FILENAME% ~ %fruit% %res% %DURATION%"),
+            Ok(PathBuf::from("video ~ Mango 1920x1080 1m05s"))
+        );
+        assert_eq!(
+            render_example("100%% %CODEC%"),
+            Ok(PathBuf::from("100% h264"))
+        );
+    }
+
+    #[test]
+    fn drops_groups_with_missing_variables() {
+        // interpolation is disabled, so its fps has no value
+        assert_eq!(
+            render_example("%FILENAME%{ ~ %INTERP_FPS% fps}{ ~ %BLUR_AMOUNT% blur}"),
+            Ok(PathBuf::from("video ~ 1.5 blur"))
+        );
+
+        let source = Source {
+            basename: "video".to_owned(),
+            ..Source::default()
+        };
+        assert_eq!(
+            render_with("%FILENAME%{ (%RES%)}", &source),
+            Ok(PathBuf::from("video"))
+        );
+    }
+
+    #[test]
+    fn nests_groups() {
+        assert_eq!(
+            render_example("%FILENAME%{ ~ %OUTPUT_FPS%fps{ from %INTERP_FPS%}}"),
+            Ok(PathBuf::from("video ~ 60fps"))
+        );
+        assert_eq!(
+            render_example("%FILENAME%{ ~ {%INTERP_FPS%} %OUTPUT_FPS%fps}"),
+            Ok(PathBuf::from("video ~  60fps"))
+        );
+        // a missing variable in the outer group drops the inner one with it
+        assert_eq!(
+            render_example("%FILENAME%{ ~ %INTERP_FPS%{ %OUTPUT_FPS%fps}}"),
+            Ok(PathBuf::from("video"))
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_templates() {
+        assert_eq!(
+            render_example("%FILENAME%{ ~ %OUTPUT_FPS%"),
+            Err("Found a `{` without a matching `}`".to_owned())
+        );
+        assert_eq!(
+            render_example("%FILENAME%}"),
+            Err("Found a `}` without a matching `{`".to_owned())
+        );
+        assert_eq!(
+            render_example("%FILENAME"),
+            Err("Unclosed variable `%FILENAME`".to_owned())
+        );
+        assert_eq!(
+            render_example("%FILENAME%{ %NOPE%}"),
+            Err("Unknown variable `%NOPE%` in `[output] file format:`".to_owned())
+        );
+        assert_eq!(
+            render_example("{%INTERP_FPS%}"),
+            Err("`[output] file format:` \"{%INTERP_FPS%}\" gives an empty filename".to_owned())
+        );
+    }
+
+    #[test]
+    fn stays_inside_the_output_folder() {
+        assert_eq!(
+            render_example("../../%FILENAME%"),
+            Ok(PathBuf::from("video"))
+        );
+        assert_eq!(
+            render_example("renders/../%FILENAME%"),
+            Ok(Path::new("renders").join("video"))
+        );
+        assert_eq!(
+            render_example("/etc/%FILENAME%"),
+            Ok(Path::new("etc").join("video"))
+        );
+        assert_eq!(
+            render_example("\\\\server\\share\\%FILENAME%"),
+            Ok(Path::new("server").join("share").join("video"))
+        );
+        assert_eq!(
+            render_example("C:/%FILENAME%"),
+            Ok(Path::new("C_").join("video"))
+        );
+    }
+
+    #[test]
+    fn variables_cant_make_folders() {
+        let source = Source {
+            basename: "../../etc/passwd".to_owned(),
+            ..Source::example()
+        };
+        assert_eq!(
+            render_with("%FILENAME%", &source),
+            Ok(PathBuf::from(".._.._etc_passwd"))
+        );
+
+        let source = Source {
+            basename: "..".to_owned(),
+            ..Source::example()
+        };
+        assert!(render_with("%FILENAME%", &source).is_err());
+    }
+}
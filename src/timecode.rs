@@ -0,0 +1,437 @@
+use crate::video::Timecodes;
+use ffprobe::FfProbe;
+use num_rational::Ratio;
+use std::fmt;
+
+/// A point in time in seconds, kept as a fraction so frame numbers at 60000/1001 stay exact
+pub type Time = Ratio<i64>;
+
+/// What's needed to resolve frame numbers, percentages and `EOF`
+#[derive(Debug, Clone, Default)]
+pub struct Context {
+    pub fps: Option<Ratio<i64>>,
+    pub duration: Option<Time>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TimecodeError {
+    Empty,
+    Invalid {
+        timecode: String,
+        reason: String,
+    },
+    Negative {
+        timecode: String,
+    },
+    MissingFps {
+        timecode: String,
+    },
+    MissingDuration {
+        timecode: String,
+    },
+    Reversed {
+        index: usize,
+        start: String,
+        end: String,
+    },
+    OutOfRange {
+        index: usize,
+        timecode: String,
+        duration: String,
+    },
+}
+
+impl fmt::Display for TimecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimecodeError::Empty => write!(f, "No cuts were given"),
+            TimecodeError::Invalid { timecode, reason } => {
+                write!(f, "Invalid timecode {timecode:?}: {reason}")
+            }
+            TimecodeError::Negative { timecode } => {
+                write!(f, "Timecode {timecode:?} is negative")
+            }
+            TimecodeError::MissingFps { timecode } => write!(
+                f,
+                "Timecode {timecode:?} is a frame number but the input's frame rate is unknown"
+            ),
+            TimecodeError::MissingDuration { timecode } => write!(
+                f,
+                "Timecode {timecode:?} is relative to the end but the input's duration is unknown"
+            ),
+            TimecodeError::Reversed { index, start, end } => write!(
+                f,
+                "Cut #{index} ends ({end}) before or where it starts ({start})"
+            ),
+            TimecodeError::OutOfRange {
+                index,
+                timecode,
+                duration,
+            } => write!(
+                f,
+                "Cut #{index} starts at {timecode}, after the end of the input ({duration})"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TimecodeError {}
+
+impl Context {
+    pub fn from_probe(probe: &FfProbe) -> Context {
+        let fps = probe
+            .streams
+            .iter()
+            .find(|stream| stream.codec_type == Some("video".to_owned()))
+            .and_then(|stream| parse_fraction(&stream.avg_frame_rate));
+
+        let duration = probe
+            .format
+            .duration
+            .as_ref()
+            .and_then(|duration| parse_decimal(duration).ok());
+
+        Context { fps, duration }
+    }
+}
+
+/// ffprobe's `60000/1001`
+fn parse_fraction(fraction: &str) -> Option<Ratio<i64>> {
+    let (num, den) = fraction.split_once('/')?;
+    let (num, den) = (
+        num.trim().parse::<i64>().ok()?,
+        den.trim().parse::<i64>().ok()?,
+    );
+
+    if num == 0 || den == 0 {
+        None
+    } else {
+        Some(Ratio::new(num, den))
+    }
+}
+
+/// `12.345` => 12345/1000, without going through a float
+pub fn parse_decimal(decimal: &str) -> Result<Time, String> {
+    let decimal = decimal.trim();
+    let (whole, fraction) = decimal.split_once('.').unwrap_or((decimal, ""));
+
+    if whole.is_empty() && fraction.is_empty() {
+        return Err("expected a number".to_owned());
+    }
+    if !whole.chars().all(|c| c.is_ascii_digit()) || !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return Err(format!("{decimal:?} is not a number"));
+    }
+    if fraction.len() > 9 {
+        return Err("more than 9 decimals".to_owned());
+    }
+
+    let whole = if whole.is_empty() {
+        0
+    } else {
+        whole.parse::<i64>().map_err(|e| e.to_string())?
+    };
+    let scale = 10i64.pow(fraction.len() as u32);
+    let fraction = if fraction.is_empty() {
+        0
+    } else {
+        fraction.parse::<i64>().map_err(|e| e.to_string())?
+    };
+
+    Ok(Ratio::new(whole * scale + fraction, scale))
+}
+
+/// Parses a single timecode, which can be any of:
+/// * seconds: `12`, `12.5`
+/// * clock time: `1:02`, `01:02:03.250`
+/// * frame numbers: `f120`, `120f`
+/// * a percentage of the input's duration: `50%`
+/// * `EOF`, the end of the input
+pub fn parse(timecode: &str, ctx: &Context) -> Result<Time, TimecodeError> {
+    let tc = timecode.trim();
+    let invalid = |reason: String| TimecodeError::Invalid {
+        timecode: timecode.to_owned(),
+        reason,
+    };
+
+    if tc.is_empty() {
+        return Err(invalid("empty timecode".to_owned()));
+    }
+    if tc.starts_with('-') {
+        return Err(TimecodeError::Negative {
+            timecode: timecode.to_owned(),
+        });
+    }
+
+    if tc.eq_ignore_ascii_case("eof") {
+        return ctx.duration.ok_or(TimecodeError::MissingDuration {
+            timecode: timecode.to_owned(),
+        });
+    }
+
+    if let Some(percent) = tc.strip_suffix('%') {
+        let duration = ctx.duration.ok_or(TimecodeError::MissingDuration {
+            timecode: timecode.to_owned(),
+        })?;
+        return Ok(duration * parse_decimal(percent).map_err(invalid)? / 100);
+    }
+
+    if let Some(frame) = tc
+        .strip_prefix('f')
+        .or_else(|| tc.strip_suffix('f'))
+        .or_else(|| tc.strip_prefix('#'))
+    {
+        let fps = ctx.fps.ok_or(TimecodeError::MissingFps {
+            timecode: timecode.to_owned(),
+        })?;
+        let frame = frame
+            .trim()
+            .parse::<i64>()
+            .map_err(|e| invalid(format!("bad frame number ({e})")))?;
+        return Ok(Ratio::from_integer(frame) / fps);
+    }
+
+    let parts: Vec<&str> = tc.split(':').collect();
+    if parts.len() > 3 {
+        return Err(invalid("expected at most HH:MM:SS".to_owned()));
+    }
+
+    let mut time = Ratio::from_integer(0);
+    for (i, part) in parts.iter().enumerate() {
+        let is_last = i == parts.len() - 1;
+        let value = if is_last {
+            parse_decimal(part).map_err(invalid)?
+        } else {
+            Ratio::from_integer(
+                part.trim()
+                    .parse::<i64>()
+                    .map_err(|e| invalid(format!("{part:?} ({e})")))?,
+            )
+        };
+
+        if i > 0 && value >= Ratio::from_integer(60) {
+            return Err(invalid(format!("{part:?} must be lower than 60")));
+        }
+        time = time * 60 + value;
+    }
+
+    Ok(time)
+}
+
+/// Parses and validates cuts, a cut overlapping or touching the one before it is merged into it.
+/// They're kept in the order they were given, montages (`--json`) play them in that order
+pub fn normalize(cuts: &[Timecodes], ctx: &Context) -> Result<Vec<(Time, Time)>, TimecodeError> {
+    if cuts.is_empty() {
+        return Err(TimecodeError::Empty);
+    }
+
+    let mut ranges: Vec<(Time, Time)> = vec![];
+
+    for (index, cut) in cuts.iter().enumerate() {
+        let start = parse(&cut.start, ctx)?;
+        let mut end = parse(&cut.fin, ctx)?;
+
+        if let Some(duration) = ctx.duration {
+            if start >= duration {
+                return Err(TimecodeError::OutOfRange {
+                    index,
+                    timecode: cut.start.clone(),
+                    duration: format_clock(duration),
+                });
+            }
+            // NLE scripts like to round the last cut up a little
+            end = end.min(duration);
+        }
+
+        if end <= start {
+            return Err(TimecodeError::Reversed {
+                index,
+                start: cut.start.clone(),
+                end: cut.fin.clone(),
+            });
+        }
+
+        ranges.push((start, end));
+    }
+
+    let mut merged: Vec<(Time, Time)> = vec![];
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 && end >= last.0 => {
+                last.0 = last.0.min(start);
+                last.1 = last.1.max(end);
+            }
+            _ => merged.push((start, end)),
+        }
+    }
+
+    Ok(merged)
+}
+
+/// Seconds with microsecond precision, rounded up so flooring to a frame lands on the right one
+pub fn format_secs(time: Time) -> String {
+    let micros = (time * 1_000_000).ceil().to_integer();
+    let formatted = format!("{}.{:06}", micros / 1_000_000, micros % 1_000_000);
+
+    formatted
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_owned()
+}
+
+/// `HH:MM:SS.mmm`, for humans
+pub fn format_clock(time: Time) -> String {
+    let millis = (time * 1000).round().to_integer();
+
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+pub fn to_timecodes(ranges: &[(Time, Time)]) -> Vec<Timecodes> {
+    ranges
+        .iter()
+        .map(|(start, end)| Timecodes {
+            start: format_secs(*start),
+            fin: format_secs(*end),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ctx() -> Context {
+        Context {
+            fps: Some(Ratio::new(60000, 1001)),
+            duration: Some(Ratio::from_integer(120)),
+        }
+    }
+
+    fn cuts(cuts: &[(&str, &str)]) -> Vec<Timecodes> {
+        cuts.iter()
+            .map(|(start, fin)| Timecodes {
+                start: start.to_string(),
+                fin: fin.to_string(),
+            })
+            .collect()
+    }
+
+    fn secs(num: i64, den: i64) -> Time {
+        Ratio::new(num, den)
+    }
+
+    #[test]
+    fn parses_seconds() {
+        assert_eq!(parse("12", &ctx()), Ok(secs(12, 1)));
+        assert_eq!(parse(" 12.5 ", &ctx()), Ok(secs(25, 2)));
+        assert_eq!(parse(".25", &ctx()), Ok(secs(1, 4)));
+    }
+
+    #[test]
+    fn parses_clock_time() {
+        assert_eq!(parse("1:02", &ctx()), Ok(secs(62, 1)));
+        assert_eq!(parse("01:02:03.250", &ctx()), Ok(secs(3723250, 1000)));
+        assert_eq!(format_clock(secs(3723250, 1000)), "01:02:03.250");
+    }
+
+    #[test]
+    fn parses_frames() {
+        let frame = secs(120 * 1001, 60000);
+        assert_eq!(parse("f120", &ctx()), Ok(frame));
+        assert_eq!(parse("120f", &ctx()), Ok(frame));
+        assert_eq!(parse("#120", &ctx()), Ok(frame));
+    }
+
+    #[test]
+    fn parses_percentages_and_eof() {
+        assert_eq!(parse("50%", &ctx()), Ok(secs(60, 1)));
+        assert_eq!(parse("12.5%", &ctx()), Ok(secs(15, 1)));
+        assert_eq!(parse("EOF", &ctx()), Ok(secs(120, 1)));
+    }
+
+    #[test]
+    fn rejects_bad_timecodes() {
+        let err = |timecode: &str, ctx: &Context| parse(timecode, ctx).unwrap_err().to_string();
+
+        assert_eq!(err("-5", &ctx()), "Timecode \"-5\" is negative");
+        assert_eq!(
+            err("1:60", &ctx()),
+            "Invalid timecode \"1:60\": \"60\" must be lower than 60"
+        );
+        assert_eq!(
+            err("1:2:3:4", &ctx()),
+            "Invalid timecode \"1:2:3:4\": expected at most HH:MM:SS"
+        );
+        assert_eq!(
+            err("abc", &ctx()),
+            "Invalid timecode \"abc\": \"abc\" is not a number"
+        );
+        assert_eq!(
+            err("f120", &Context::default()),
+            "Timecode \"f120\" is a frame number but the input's frame rate is unknown"
+        );
+        assert_eq!(
+            err("50%", &Context::default()),
+            "Timecode \"50%\" is relative to the end but the input's duration is unknown"
+        );
+    }
+
+    #[test]
+    fn rejects_reversed_cuts() {
+        let err = normalize(&cuts(&[("0", "5"), ("10", "8")]), &ctx()).unwrap_err();
+        assert_eq!(
+            err,
+            TimecodeError::Reversed {
+                index: 1,
+                start: "10".to_owned(),
+                end: "8".to_owned(),
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "Cut #1 ends (8) before or where it starts (10)"
+        );
+    }
+
+    #[test]
+    fn rejects_cuts_past_the_end() {
+        let err = normalize(&cuts(&[("2:01", "2:05")]), &ctx()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Cut #0 starts at 2:01, after the end of the input (00:02:00.000)"
+        );
+        assert_eq!(
+            normalize(&cuts(&[]), &ctx()).unwrap_err().to_string(),
+            "No cuts were given"
+        );
+    }
+
+    #[test]
+    fn clamps_the_end_to_the_duration() {
+        assert_eq!(
+            normalize(&cuts(&[("1:50", "2:10")]), &ctx()),
+            Ok(vec![(secs(110, 1), secs(120, 1))])
+        );
+    }
+
+    #[test]
+    fn merges_overlapping_cuts() {
+        assert_eq!(
+            normalize(&cuts(&[("0", "10"), ("5", "15"), ("15", "20")]), &ctx()),
+            Ok(vec![(secs(0, 1), secs(20, 1))])
+        );
+    }
+
+    #[test]
+    fn keeps_the_given_order() {
+        assert_eq!(
+            normalize(&cuts(&[("30", "40"), ("0", "10"), ("8", "12")]), &ctx()),
+            Ok(vec![(secs(30, 1), secs(40, 1)), (secs(0, 1), secs(12, 1))])
+        );
+    }
+}
@@ -0,0 +1,429 @@
+use crate::verb;
+use colored::Colorize;
+use std::env;
+use std::fmt;
+use std::io::{BufRead, BufReader};
+use std::process::{ChildStderr, ExitStatus};
+use std::thread::{self, JoinHandle};
+
+/// The deepest Python frame that isn't VapourSynth's own cython
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub script: String,
+    pub line: u32,
+    pub code: Option<String>,
+}
+
+/// Why a render failed, as best as can be told from VapourSynth's and FFmpeg's output
+#[derive(Debug, Clone, PartialEq)]
+pub enum Failure {
+    /// jamba.vpy (or one of its scripts) raised
+    Script {
+        frame: Option<Frame>,
+        exception: String,
+        message: String,
+    },
+    /// A VapourSynth plugin the recipe needs is not installed
+    MissingPlugin {
+        plugin: String,
+        frame: Option<Frame>,
+        message: String,
+    },
+    /// VapourSynth fed FFmpeg fine but FFmpeg could not encode it
+    Encoder {
+        status: Option<i32>,
+        errors: Vec<String>,
+    },
+    /// vspipe (or the in-process script) stopped on its own without a Python exception
+    VapourSynth {
+        status: Option<i32>,
+        output: Vec<String>,
+    },
+}
+
+impl Failure {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Failure::Script { .. } => "script",
+            Failure::MissingPlugin { .. } => "missing_plugin",
+            Failure::Encoder { .. } => "encoder",
+            Failure::VapourSynth { .. } => "vapoursynth",
+        }
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}, line {}", self.script, self.line)?;
+        if let Some(code) = &self.code {
+            write!(f, "\n    {code}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Script {
+                frame,
+                exception,
+                message,
+            } => {
+                write!(f, "VapourSynth script error, {exception}: {message}")?;
+                if let Some(frame) = frame {
+                    write!(f, "\n  in {frame}")?;
+                }
+                Ok(())
+            }
+            Failure::MissingPlugin {
+                plugin,
+                frame,
+                message,
+            } => {
+                write!(
+                    f,
+                    "Missing VapourSynth plugin `{plugin}`, make sure it's installed where VapourSynth looks for plugins\n  {message}"
+                )?;
+                if let Some(frame) = frame {
+                    write!(f, "\n  in {frame}")?;
+                }
+                Ok(())
+            }
+            Failure::Encoder { status, errors } => {
+                write!(f, "FFmpeg failed encoding ({})", format_status(*status))?;
+                for error in errors {
+                    write!(f, "\n  {error}")?;
+                }
+                Ok(())
+            }
+            Failure::VapourSynth { status, output } => {
+                match status {
+                    Some(code) => write!(f, "VapourSynth failed (exit code {code})")?,
+                    None => write!(f, "VapourSynth failed")?,
+                }
+                for line in output {
+                    write!(f, "\n  {line}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+fn format_status(status: Option<i32>) -> String {
+    match status {
+        Some(code) => format!("exit code {code}"),
+        None => "killed".to_owned(),
+    }
+}
+
+/// Where vspipe stops logging and starts explaining why it died
+fn starts_error(line: &str) -> bool {
+    line.starts_with("Script evaluation failed")
+        || line.starts_with("Python exception:")
+        || line.starts_with("Traceback (most recent call last)")
+}
+
+/// Reads vspipe's stderr on the side: jamba.vpy's own `VERB:` / `ERR:` lines are passed through as they come,
+/// the traceback is kept for [diagnose] instead of being dumped
+pub fn capture(stderr: ChildStderr) -> JoinHandle<Vec<String>> {
+    thread::spawn(move || {
+        let mut lines = vec![];
+        let mut erroring = false;
+
+        for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+            erroring |= starts_error(&line);
+            if !erroring && !line.trim().is_empty() {
+                eprintln!("{line}");
+            }
+            lines.push(line);
+        }
+        lines
+    })
+}
+
+/// `  File "C:\smoothie\jamba.vpy", line 97, in <module>`
+fn parse_frame(line: &str) -> Option<(String, u32)> {
+    let rest = line.trim().strip_prefix("File \"")?;
+    let (script, rest) = rest.split_once("\", line ")?;
+    let line = rest
+        .split(|c: char| !c.is_ascii_digit())
+        .next()?
+        .parse()
+        .ok()?;
+    Some((script.to_owned(), line))
+}
+
+/// Plugin namespaces VapourSynth complains about, `No attribute with the name ffms2 exists`
+fn missing_plugin(message: &str) -> Option<String> {
+    let plugin = if let Some(rest) = message.split("No attribute with the name ").nth(1) {
+        rest.split_whitespace().next()?
+    } else if let Some(rest) = message.split("There is no function named ").nth(1) {
+        rest.split_whitespace().next()?
+    } else if message.contains("Failed to load") || message.contains("Plugin load of") {
+        message.split(['"', '\'']).nth(1).unwrap_or("unknown")
+    } else {
+        return None;
+    };
+
+    Some(plugin.trim_end_matches(['.', ',']).to_owned())
+}
+
+/// Pulls the script, line and message out of a Python traceback, None if there isn't one
+pub fn parse(lines: &[String]) -> Option<Failure> {
+    let start = lines.iter().position(|line| starts_error(line))?;
+    let lines = &lines[start..];
+
+    let mut frame: Option<Frame> = None;
+    for (i, line) in lines.iter().enumerate() {
+        if let Some((script, line_number)) = parse_frame(line) {
+            // VapourSynth's cython wrapper is never what needs fixing
+            if script.ends_with(".pyx") {
+                continue;
+            }
+            let code = lines
+                .get(i + 1)
+                .filter(|next| parse_frame(next).is_none() && next.starts_with("    "))
+                .map(|next| next.trim().to_owned());
+            frame = Some(Frame {
+                script,
+                line: line_number,
+                code,
+            });
+        }
+    }
+
+    // the last `SomeError: message` line, or vspipe's own `Python exception:` summary
+    let (exception, message) = lines
+        .iter()
+        .rev()
+        .filter(|line| !line.starts_with(' '))
+        .find_map(|line| {
+            let (exception, message) = line.split_once(": ")?;
+            let is_exception = !exception.is_empty()
+                && exception
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || c == '_' || c == '.');
+            is_exception.then(|| (exception.to_owned(), message.trim().to_owned()))
+        })
+        .or_else(|| {
+            lines.iter().find_map(|line| {
+                line.strip_prefix("Python exception:")
+                    .map(|message| ("Exception".to_owned(), message.trim().to_owned()))
+            })
+        })
+        .unwrap_or_else(|| ("Exception".to_owned(), lines.join(" ").trim().to_owned()));
+
+    Some(match missing_plugin(&message) {
+        Some(plugin) => Failure::MissingPlugin {
+            plugin,
+            frame,
+            message,
+        },
+        None => Failure::Script {
+            frame,
+            exception,
+            message,
+        },
+    })
+}
+
+/// A traceback if VapourSynth left one, the end of its output otherwise
+pub fn vapoursynth_failure(status: Option<i32>, log: &[String]) -> Failure {
+    if let Some(failure) = parse(log) {
+        for line in log.iter().skip_while(|line| !starts_error(line)) {
+            verb!("VapourSynth> {line}");
+        }
+        return failure;
+    }
+
+    let output: Vec<String> = log
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .cloned()
+        .collect();
+    Failure::VapourSynth {
+        status,
+        output: output[output.len().saturating_sub(10)..].to_vec(),
+    }
+}
+
+/// Tells apart why a render failed: a traceback from VapourSynth wins, since FFmpeg always fails too when its input stops short,
+/// then FFmpeg's own failure, then VapourSynth stopping without saying why.
+/// `vs_ok` / `vs_status` / `vs_log` are vspipe's exit and stderr, or the in-process script's
+pub fn diagnose(
+    vs_ok: bool,
+    vs_status: Option<i32>,
+    vs_log: &[String],
+    ffmpeg: ExitStatus,
+    ffmpeg_errors: &[String],
+) -> Option<Failure> {
+    if vs_ok && ffmpeg.success() {
+        return None;
+    }
+
+    if parse(vs_log).is_some() || ffmpeg.success() {
+        return Some(vapoursynth_failure(vs_status, vs_log));
+    }
+
+    Some(Failure::Encoder {
+        status: ffmpeg.code(),
+        errors: ffmpeg_errors.to_vec(),
+    })
+}
+
+/// Prints a failure in red, bold header first
+pub fn report(failure: &Failure) {
+    let text = failure.to_string();
+    let (header, details) = text.split_once('\n').unwrap_or((&text, ""));
+
+    eprintln!("{}", header.red().bold());
+    if !details.is_empty() {
+        eprintln!("{}", details.red());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCRIPT_ERROR: &str = r#"VERB: Loading C:\smoothie\target\jamba.vpy
+Script evaluation failed:
+Python exception: unsupported operand type(s) for /: 'str' and 'int'
+
+Traceback (most recent call last):
+  File "src/cython/vapoursynth.pyx", line 3365, in vapoursynth._vpy_evaluate
+  File "src/cython/vapoursynth.pyx", line 3366, in vapoursynth._vpy_evaluate
+  File "C:\smoothie\target\jamba.vpy", line 97, in <module>
+    video = blending.average(video, fps / 2, weights)
+TypeError: unsupported operand type(s) for /: 'str' and 'int'
+"#;
+
+    const MISSING_PLUGIN: &str = r#"Script evaluation failed:
+Python exception: No attribute with the name mv exists. Did you mistype a plugin namespace or forget to install a plugin?
+
+Traceback (most recent call last):
+  File "src/cython/vapoursynth.pyx", line 3365, in vapoursynth._vpy_evaluate
+  File "C:\smoothie\target\jamba.vpy", line 58, in <module>
+    video = havsfunc.InterFrame(video, GPU=True)
+  File "C:\smoothie\target\havsfunc.py", line 412, in InterFrame
+    super = core.mv.Super(clip)
+  File "src/cython/vapoursynth.pyx", line 2069, in vapoursynth.Core.__getattr__
+AttributeError: No attribute with the name mv exists. Did you mistype a plugin namespace or forget to install a plugin?
+"#;
+
+    const ENCODER_ERRORS: &[&str] = &[
+        "[vost#0:0/h264_nvenc @ 0x55d0c8] [error] OpenEncodeSessionEx failed: unsupported device (2): (no details)",
+        "[vost#0:0/h264_nvenc @ 0x55d0c8] [error] Error while opening encoder - maybe incorrect parameters such as bit_rate, rate, width or height.",
+    ];
+
+    fn lines(log: &str) -> Vec<String> {
+        log.lines().map(String::from).collect()
+    }
+
+    #[cfg(unix)]
+    fn exit(code: i32) -> ExitStatus {
+        std::os::unix::process::ExitStatusExt::from_raw(code << 8)
+    }
+
+    #[test]
+    fn parses_a_python_traceback() {
+        assert_eq!(
+            parse(&lines(SCRIPT_ERROR)),
+            Some(Failure::Script {
+                frame: Some(Frame {
+                    script: r"C:\smoothie\target\jamba.vpy".to_owned(),
+                    line: 97,
+                    code: Some("video = blending.average(video, fps / 2, weights)".to_owned()),
+                }),
+                exception: "TypeError".to_owned(),
+                message: "unsupported operand type(s) for /: 'str' and 'int'".to_owned(),
+            })
+        );
+    }
+
+    #[test]
+    fn parses_a_missing_plugin() {
+        let failure = parse(&lines(MISSING_PLUGIN)).unwrap();
+        assert_eq!(failure.kind(), "missing_plugin");
+        match failure {
+            Failure::MissingPlugin { plugin, frame, .. } => {
+                assert_eq!(plugin, "mv");
+                // the deepest frame that's not cython
+                let frame = frame.unwrap();
+                assert_eq!(frame.script, r"C:\smoothie\target\havsfunc.py");
+                assert_eq!(frame.line, 412);
+                assert_eq!(frame.code.as_deref(), Some("super = core.mv.Super(clip)"));
+            }
+            other => panic!("expected a missing plugin, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn no_traceback_in_a_plain_log() {
+        assert_eq!(
+            parse(&lines("VERB: Loading jamba.vpy\nOutput 1200 frames")),
+            None
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_traceback_wins_over_ffmpeg() {
+        // FFmpeg fails too when its input stops short
+        let failure = diagnose(
+            false,
+            Some(1),
+            &lines(SCRIPT_ERROR),
+            exit(1),
+            &["[error] Error while decoding stream #0:0".to_owned()],
+        );
+        assert_eq!(failure.unwrap().kind(), "script");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn diagnoses_an_encoder_failure() {
+        let errors: Vec<String> = ENCODER_ERRORS.iter().map(|e| e.to_string()).collect();
+        let failure = diagnose(
+            false,
+            Some(1),
+            &lines("VERB: Loading jamba.vpy\nError: fwrite() call failed when writing frame: 0"),
+            exit(187),
+            &errors,
+        )
+        .unwrap();
+
+        assert_eq!(
+            failure,
+            Failure::Encoder {
+                status: Some(187),
+                errors: errors.clone(),
+            }
+        );
+        assert!(failure
+            .to_string()
+            .starts_with("FFmpeg failed encoding (exit code 187)\n  [vost#0:0/h264_nvenc"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn vapoursynth_stopping_on_its_own() {
+        let failure = diagnose(
+            false,
+            Some(1),
+            &lines("Failed to retrieve frame 12"),
+            exit(0),
+            &[],
+        );
+        assert_eq!(
+            failure,
+            Some(Failure::VapourSynth {
+                status: Some(1),
+                output: vec!["Failed to retrieve frame 12".to_owned()],
+            })
+        );
+        assert_eq!(diagnose(true, Some(0), &[], exit(0), &[]), None);
+    }
+}
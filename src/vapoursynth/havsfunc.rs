@@ -1,47 +1,34 @@
-use rustsynth::{
-    core::CoreRef,
-    function::Function,
-    node::Node,
-    owned_map,
-    prelude::{Map, API},
-};
+use rustsynth::{core::CoreRef, function::Function, node::Node, owned_map, prelude::Map};
 
 pub fn _change_fps<'elem, 'core: 'elem>(
-    api: API,
     core: CoreRef<'core>,
     clip: Node<'core>,
-    fpsnum: f64,
-    fpsden: f64,
+    fpsnum: i64,
+    fpsden: i64,
 ) -> Node<'elem> {
-    let factor = (fpsnum / fpsden)
+    let factor = (fpsnum as f64 / fpsden as f64)
         * (clip.video_info().unwrap().fps_den as f64 / clip.video_info().unwrap().fps_num as f64);
 
-    let length = (clip.video_info().unwrap().num_frames as f64 * factor).floor();
-    let adjust_frame = move |api: API,
-                             core: CoreRef<'core>,
-                             in_map: &Map<'core>,
-                             out: &mut Map<'core>| {
+    let length = (clip.video_info().unwrap().num_frames as f64 * factor).floor() as i64;
+    let adjust_frame = move |core: CoreRef<'core>, in_map: &Map<'core>, out: &mut Map<'core>| {
         let n = in_map.get_int("n").unwrap();
-        let real_n = (n as f64 / factor).floor();
+        let real_n = (n as f64 / factor).floor() as i64;
         let std = core.plugin_by_namespace("std").unwrap();
-        let in_args = owned_map!(api, { "clip": &clip }, { "first": &real_n }, {
-            "last": &real_n
-        });
+        let in_args = owned_map!({ "clip": &clip }, { "first": &real_n }, { "last": &real_n });
         let trim = std.invoke("Trim", &in_args);
         let one_frame_clip: Node = trim.get("clip").unwrap();
-        let in_args = owned_map!(api, {"clip": &one_frame_clip}, {"factor": &(clip.video_info().unwrap().num_frames as i64 + 100)});
+        let in_args = owned_map!({"clip": &one_frame_clip}, {"times": &(clip.video_info().unwrap().num_frames as i64 + 100)});
         let one_frame_clip = std.invoke("Loop", &in_args);
         let node = one_frame_clip.get_node("clip").unwrap();
         out.set_node("clip", &node).unwrap();
     };
     let adjust_frame = Function::new(core, adjust_frame);
     let std = core.plugin_by_namespace("std").unwrap();
-    let in_args = owned_map!(api, { "length": &length }, { "fpsnum": &fpsnum }, {
+    let in_args = owned_map!({ "length": &length }, { "fpsnum": &fpsnum }, {
         "fpsden": &fpsden
     });
     let blank = std.invoke("BlankClip", &in_args);
-    let in_args =
-        owned_map!(api, {"eval": &adjust_frame}, {"clip": &blank.get_node("clip").unwrap()});
+    let in_args = owned_map!({"eval": &adjust_frame}, {"clip": &blank.get_node("clip").unwrap()});
     let eval = std.invoke("FrameEval", &in_args);
     eval.get_node("clip").unwrap()
 }
@@ -0,0 +1,188 @@
+//! jamba.vpy evaluated in-process through the VapourSynth API (`--features vapoursynth`), instead of through vspipe
+
+pub mod havsfunc;
+#[allow(unused)]
+pub mod output;
+
+use num_rational::Ratio;
+use output::OutputParameters;
+use rustsynth::{
+    core::{CoreCreationFlags, CoreRef},
+    ffi,
+    map::OwnedMap,
+    vsscript::{self, Environment},
+};
+use std::{
+    error::Error,
+    path::PathBuf,
+    process::ChildStdin,
+    sync::mpsc::{channel, Sender},
+    thread::{self, JoinHandle},
+};
+
+/// What vspipe would have been asked to do, read back from the arguments cmd.rs builds for it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Invocation {
+    pub script: PathBuf,
+    /// `--arg key=value`
+    pub variables: Vec<(String, String)>,
+    pub start: Option<usize>,
+    pub end: Option<usize>,
+}
+
+impl Invocation {
+    pub fn parse(vs_args: &[String]) -> Invocation {
+        let mut invocation = Invocation::default();
+        let mut args = vs_args.iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--arg" | "-a" => {
+                    if let Some((key, value)) = args.next().and_then(|var| var.split_once('=')) {
+                        invocation
+                            .variables
+                            .push((key.to_owned(), value.to_owned()));
+                    }
+                }
+                "--start" | "-s" => invocation.start = args.next().and_then(|n| n.parse().ok()),
+                "--end" | "-e" => invocation.end = args.next().and_then(|n| n.parse().ok()),
+                "--container" | "-c" => {
+                    args.next();
+                }
+                "-" => {}
+                script => invocation.script = PathBuf::from(script),
+            }
+        }
+        invocation
+    }
+}
+
+/// VSScript's own message (the Python traceback) sits in the error's source
+fn script_error(e: vsscript::Error) -> String {
+    match e.source() {
+        Some(source) => source.to_string(),
+        None => e.to_string(),
+    }
+}
+
+/// A script being rendered on its own thread, frames go to FFmpeg's stdin once it's handed over
+pub struct InProcess {
+    /// Exact amount of frames that will be output
+    pub frames: usize,
+    pub fps: Ratio<i64>,
+    stdin: Sender<ChildStdin>,
+    thread: JoinHandle<Result<(), String>>,
+}
+
+impl InProcess {
+    /// Evaluates the script, Err holds VapourSynth's error (a Python traceback if the script raised)
+    pub fn start(vs_args: &[String]) -> Result<InProcess, String> {
+        let invocation = Invocation::parse(vs_args);
+        let (info_sender, info) = channel::<Result<(usize, Ratio<i64>), String>>();
+        let (stdin, stdin_receiver) = channel::<ChildStdin>();
+
+        // the environment, core and nodes all borrow each other, so they live and die on this thread
+        let thread = thread::spawn(move || {
+            let evaluated = (|| {
+                // rustsynth unwraps the API when making a core, check it's there first
+                let api = unsafe { ffi::getVapourSynthAPI(ffi::VAPOURSYNTH_API_VERSION as i32) };
+                if api.is_null() {
+                    return Err("Failed loading the VapourSynth API, is it installed?".to_owned());
+                }
+                // VSScript takes ownership of the core, it's freed along with the environment
+                let core = CoreRef::new(CoreCreationFlags::NONE);
+
+                let mut variables = OwnedMap::new();
+                for (key, value) in &invocation.variables {
+                    variables
+                        .set_data(key, value.as_bytes())
+                        .map_err(|e| format!("Failed passing {key} to the script: {e}"))?;
+                }
+
+                let mut environment = Environment::new(&core).map_err(script_error)?;
+                environment
+                    .set_variables(&variables)
+                    .map_err(script_error)?;
+                environment
+                    .eval_file(&invocation.script)
+                    .map_err(script_error)?;
+                Ok::<_, String>((core, environment))
+            })();
+
+            let (core, environment) = match evaluated {
+                Ok(evaluated) => evaluated,
+                Err(e) => {
+                    let _ = info_sender.send(Err(e.clone()));
+                    return Err(e);
+                }
+            };
+            let node = environment.get_output(0).ok_or_else(|| {
+                let e = "The script did not set an output".to_owned();
+                let _ = info_sender.send(Err(e.clone()));
+                e
+            })?;
+
+            let info = node
+                .video_info()
+                .ok_or("The script's output has no video info")?;
+            let last = (info.num_frames as usize).saturating_sub(1);
+            let start_frame = invocation.start.unwrap_or(0).min(last);
+            let end_frame = invocation.end.unwrap_or(last).clamp(start_frame, last);
+
+            let _ = info_sender.send(Ok((
+                end_frame - start_frame + 1,
+                Ratio::new(info.fps_num as i64, info.fps_den as i64),
+            )));
+
+            // FFmpeg failed to start, nothing to render to
+            let Ok(stdin) = stdin_receiver.recv() else {
+                return Ok(());
+            };
+
+            let requests = core.info().num_threads.max(1);
+
+            output::output(
+                stdin,
+                None,
+                OutputParameters {
+                    node,
+                    start_frame,
+                    end_frame,
+                    requests,
+                    y4m: true,
+                },
+            )
+            .map_err(|e| format!("{e:#}"))
+        });
+
+        match info.recv() {
+            Ok(Ok((frames, fps))) => Ok(InProcess {
+                frames,
+                fps,
+                stdin,
+                thread,
+            }),
+            Ok(Err(e)) => {
+                let _ = thread.join();
+                Err(e)
+            }
+            Err(_) => Err(match thread.join() {
+                Ok(Err(e)) => e,
+                _ => "VapourSynth crashed while evaluating the script".to_owned(),
+            }),
+        }
+    }
+
+    /// Starts sending frames to FFmpeg
+    pub fn feed(&self, stdin: ChildStdin) {
+        let _ = self.stdin.send(stdin);
+    }
+
+    /// Waits for the last frame to be sent
+    pub fn finish(self) -> Result<(), String> {
+        drop(self.stdin);
+        self.thread
+            .join()
+            .unwrap_or_else(|_| Err("VapourSynth crashed while rendering".to_owned()))
+    }
+}
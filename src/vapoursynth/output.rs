@@ -1,6 +1,8 @@
+use crate::verb;
 use std::{
     cmp,
     collections::HashMap,
+    env,
     fs::File,
     io::Write,
     process::ChildStdin,
@@ -45,8 +47,8 @@ struct SharedData<'core, T: Write> {
     output_state: Mutex<OutputState<'core, T>>,
 }
 
-fn print_y4m_header<W: Write>(writer: &mut W, node: &Node) -> Result<(), Error> {
-    let info = node.video_info().unwrap();
+fn print_y4m_header<W: Write>(writer: &mut W, parameters: &OutputParameters) -> Result<(), Error> {
+    let info = parameters.node.video_info().unwrap();
 
     let format = info.format;
     write!(writer, "YUV4MPEG2 C")?;
@@ -95,7 +97,12 @@ fn print_y4m_header<W: Write>(writer: &mut W, node: &Node) -> Result<(), Error>
 
     write!(writer, " F{}:{}", info.fps_num, info.fps_den)?;
 
-    writeln!(writer, " Ip A0:0 XLENGTH={}", info.num_frames)?;
+    // only the --start/--end range gets output
+    writeln!(
+        writer,
+        " Ip A0:0 XLENGTH={}",
+        parameters.end_frame - parameters.start_frame + 1
+    )?;
 
     Ok(())
 }
@@ -310,7 +317,7 @@ pub fn output(
 ) -> Result<(), Error> {
     // Print the y4m header.
     if parameters.y4m {
-        print_y4m_header(&mut output_target, &parameters.node)
+        print_y4m_header(&mut output_target, &parameters)
             .context("Couldn't write the y4m header")?;
     }
 
@@ -331,7 +338,7 @@ pub fn output(
         error: None,
         reorder_map: HashMap::new(),
         last_requested_frame: parameters.start_frame + initial_requests - 1,
-        next_output_frame: 0,
+        next_output_frame: parameters.start_frame,
         current_timecode: Ratio::from_integer(0),
         callbacks_fired: 0,
         callbacks_fired_alpha: 0,
@@ -351,7 +358,7 @@ pub fn output(
     // Start off by requesting some frames.
     {
         let parameters = &shared_data.output_parameters;
-        for n in 0..initial_requests {
+        for n in parameters.start_frame..parameters.start_frame + initial_requests {
             let shared_data_2 = shared_data.clone();
             parameters.node.get_frame_async(n, move |frame, n, node| {
                 frame_done_callback(frame, n, &node, &shared_data_2, false)
@@ -369,11 +376,12 @@ pub fn output(
     let elapsed_seconds = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9;
 
     let mut state = shared_data.output_state.lock().unwrap();
-    eprintln!(
+    let frames = state.next_output_frame - shared_data.output_parameters.start_frame;
+    verb!(
         "Output {} frames in {:.2} seconds ({:.2} fps)",
-        state.next_output_frame,
+        frames,
         elapsed_seconds,
-        state.next_output_frame as f64 / elapsed_seconds
+        frames as f64 / elapsed_seconds
     );
 
     if let Some((n, ref msg)) = state.error {
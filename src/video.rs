@@ -1,9 +1,19 @@
-use crate::{cli::Arguments, recipe::Recipe};
+use crate::{backend::Kind, cli::Arguments, recipe::Recipe, template, timecode, verb};
 use color_eyre::owo_colors::OwoColorize;
 use ffprobe::FfProbe;
+use num_rational::Ratio;
+use rand::rngs::StdRng;
 use rand::seq::IndexedRandom;
+use rand::{RngCore, SeedableRng};
 use rfd::FileDialog;
-use std::{collections::HashMap, fs, path::PathBuf};
+use sha2::{Digest, Sha256};
+use std::io::ErrorKind;
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 use which::which;
 
 #[derive(Debug, Clone)]
@@ -14,6 +24,8 @@ pub struct Payload {
     pub basename: String,  // Equivalent to .NET's [IO.Path]::GetFileNameWithoutExtension
     pub probe: FfProbe,    // provided by ffprobe
     pub timecodes: Option<Vec<Timecodes>>,
+    /// The placeholder partial file holding `out_path`, None if nothing gets written
+    pub reservation: Option<Arc<Reservation>>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -22,12 +34,142 @@ pub struct Timecodes {
     pub fin: String,
 }
 
+/// Average frame rate of the first video stream, parsed from ffprobe's `num/den`
+pub fn source_fps(probe: &FfProbe) -> Option<f64> {
+    let stream = probe
+        .streams
+        .iter()
+        .find(|stream| stream.codec_type == Some("video".to_owned()))?;
+
+    let (num, den) = stream.avg_frame_rate.split_once('/')?;
+    let (num, den) = (num.parse::<f64>().ok()?, den.parse::<f64>().ok()?);
+
+    if den == 0.0 {
+        None
+    } else {
+        Some(num / den)
+    }
+}
+
+/// Frame rates along jamba.vpy's chain, taking the same steps it does. The one place the audio tempo,
+/// progress totals, the FFmpeg backend and the GUI preview get their numbers from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FpsChain {
+    pub source: Ratio<i64>,
+    /// After `[runtime] fpscap`, which drops frames without changing the speed
+    pub capped: Ratio<i64>,
+    /// After `[timescale] in`, same frames played at another rate
+    pub retimed: Ratio<i64>,
+    /// After pre-interp and interpolation
+    pub interpolated: Ratio<i64>,
+    /// After `[timescale] out`, what frame blending gets fed
+    pub blending: Ratio<i64>,
+    /// After frame blending, what gets encoded
+    pub output: Ratio<i64>,
+}
+
+impl FpsChain {
+    /// None if the source's fps is unknown, or `[interpolation] fps:` / `[pre-interp] factor:` can't be parsed
+    pub fn from_probe(recipe: &Recipe, probe: &FfProbe) -> Option<FpsChain> {
+        FpsChain::new(recipe, timecode::Context::from_probe(probe).fps?)
+    }
+
+    pub fn new(recipe: &Recipe, source: Ratio<i64>) -> Option<FpsChain> {
+        let decimal = |value: &str| {
+            timecode::parse_decimal(value)
+                .ok()
+                .filter(|value| *value > Ratio::from_integer(0))
+        };
+        // `3x` multiplies the previous fps
+        let multiplied = |value: &str, fps: Ratio<i64>| match value
+            .trim()
+            .strip_suffix('x')
+            .or_else(|| value.trim().strip_prefix('x'))
+        {
+            Some(factor) => decimal(factor).map(|factor| factor * fps),
+            None => decimal(value),
+        };
+
+        let capped = match recipe
+            .get_option("runtime", "fpscap")
+            .and_then(|cap| decimal(&cap))
+        {
+            Some(cap) => cap.trunc(),
+            None => source,
+        };
+        let retimed = match recipe
+            .get_option("timescale", "in")
+            .and_then(|scale| decimal(&scale))
+        {
+            Some(scale) => capped / scale,
+            None => capped,
+        };
+
+        let mut interpolated = retimed;
+        if recipe.get_bool_or("pre-interp", "enabled", false) {
+            interpolated = multiplied(&recipe.get("pre-interp", "factor"), interpolated)?;
+        }
+        if recipe.get_bool_or("interpolation", "enabled", false) {
+            interpolated = multiplied(&recipe.get("interpolation", "fps"), interpolated)?;
+        }
+
+        let blending = match recipe
+            .get_option("timescale", "out")
+            .and_then(|scale| decimal(&scale))
+        {
+            Some(scale) if scale != Ratio::from_integer(1) => (interpolated * scale).trunc(),
+            _ => interpolated,
+        };
+
+        let mut output = blending;
+        if recipe.get_bool_or("frame blending", "enabled", false) {
+            // blending is skipped when it would not bring the fps down
+            if let Some(blended) = recipe
+                .get_option("frame blending", "fps")
+                .and_then(|blended| decimal(&blended))
+                .filter(|blended| *blended < blending + 1)
+            {
+                output = blended.trunc();
+            }
+        }
+
+        Some(FpsChain {
+            source,
+            capped,
+            retimed,
+            interpolated,
+            blending,
+            output,
+        })
+    }
+
+    /// How much faster the output plays than the source, only the timescale steps change it
+    pub fn speed(&self) -> Ratio<i64> {
+        (self.retimed / self.capped) * (self.blending / self.interpolated)
+    }
+
+    /// How many output frames there are per source frame
+    pub fn frame_ratio(&self) -> Ratio<i64> {
+        self.output / self.source / self.speed()
+    }
+}
+
+/// Frame rate jamba.vpy outputs, see [FpsChain]. None if the source's fps is unknown
+pub fn output_fps(recipe: &Recipe, probe: &FfProbe) -> Option<Ratio<i64>> {
+    FpsChain::from_probe(recipe, probe).map(|chain| chain.output)
+}
+
+/// Frame rate frame blending gets fed, i.e [output_fps] without its last step
+pub fn blending_fps(recipe: &Recipe, probe: &FfProbe) -> Option<Ratio<i64>> {
+    FpsChain::from_probe(recipe, probe).map(|chain| chain.blending)
+}
+
 /// Only returns videos that are valid (exists, ffprobe-able)
-fn probe_video(input: &PathBuf) -> Option<FfProbe> {
+pub fn probe_video(input: &PathBuf) -> Option<FfProbe> {
     let path = match input.canonicalize() {
         Ok(path) => path,
         _ => {
-            println!(
+            eprintln!(
                 "{}",
                 format!("{input:?} does not exist or is not a valid filepath, discarding..")
                     .on_red()
@@ -40,7 +182,7 @@ fn probe_video(input: &PathBuf) -> Option<FfProbe> {
     let file = match fs::File::open(&path) {
         Ok(file) => file,
         _ => {
-            println!("Error opening input file: {path:?}");
+            eprintln!("Error opening input file: {path:?}");
             return None;
         }
     };
@@ -48,7 +190,7 @@ fn probe_video(input: &PathBuf) -> Option<FfProbe> {
     // Check if the file is empty (0 bytes)
     let metadata = file.metadata().expect("Error getting input file metadata");
     if metadata.len() == 0 {
-        println!(
+        eprintln!(
             "{:?} is an empty file (0 bytes), discarding..",
             path.file_name().expect("Failing getting input filename")
         );
@@ -69,16 +211,206 @@ fn probe_video(input: &PathBuf) -> Option<FfProbe> {
     Some(probe)
 }
 
-/// Generates an output file path
+/// What to do when the output filename is already taken, from `[output] on conflict:`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConflictPolicy {
+    /// `video (2).mp4`, the default
+    Increment,
+    Overwrite,
+    /// Don't render that input
+    Skip,
+    Fail,
+}
+
+impl ConflictPolicy {
+    pub fn from_recipe(recipe: &Recipe) -> ConflictPolicy {
+        match recipe
+            .get_option("output", "on conflict")
+            .unwrap_or_default()
+            .trim()
+            .to_lowercase()
+            .as_str()
+        {
+            "" | "increment" => ConflictPolicy::Increment,
+            "overwrite" => ConflictPolicy::Overwrite,
+            "skip" => ConflictPolicy::Skip,
+            "fail" => ConflictPolicy::Fail,
+            other => panic!(
+                "Unknown `[output] on conflict:` {other:?}, expected increment, overwrite, skip or fail"
+            ),
+        }
+    }
+}
+
+/// Where FFmpeg writes until it's done, `video ~ Mango.mp4` => `video ~ Mango.partial.mp4`
+pub fn partial_path(out: &Path) -> PathBuf {
+    match out.extension() {
+        Some(ext) => out.with_extension(format!("partial.{}", ext.to_string_lossy())),
+        None => out.with_extension("partial"),
+    }
+}
+
+/// Creating the partial file is what reserves a name, so two inputs (or two smoothies) can't pick the same one
+fn reserve(out: &Path) -> std::io::Result<fs::File> {
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(partial_path(out))
+}
+
+/// An output name taken with [`reserve`], its placeholder is removed once dropped (unwinding included)
+/// if nothing was rendered to it, a finished render was already renamed and a kept partial has data
+#[derive(Debug)]
+pub struct Reservation(PathBuf);
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        let partial = partial_path(&self.0);
+        if fs::metadata(&partial).is_ok_and(|meta| meta.len() == 0) {
+            let _ = fs::remove_file(&partial);
+        }
+    }
+}
+
+/// Applies `[output] on conflict:` to a wanted output path, returns None if the input should be skipped
+fn claim_outpath(out: PathBuf, policy: ConflictPolicy) -> Option<PathBuf> {
+    let taken = out.exists() || partial_path(&out).exists();
+
+    match policy {
+        ConflictPolicy::Increment => {
+            let stem = out
+                .file_stem()
+                .expect("Failed getting output filename")
+                .to_string_lossy()
+                .to_string();
+            let ext = out.extension().map(|ext| ext.to_string_lossy().to_string());
+
+            let mut candidate = out.clone();
+            let mut round = 2;
+            loop {
+                if !candidate.exists() {
+                    match reserve(&candidate) {
+                        Ok(_) => return Some(candidate),
+                        Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+                        Err(e) => panic!("Failed creating {}: {e}", candidate.display()),
+                    }
+                }
+                candidate = out.with_file_name(match &ext {
+                    Some(ext) => format!("{stem} ({round}).{ext}"),
+                    None => format!("{stem} ({round})"),
+                });
+                round += 1;
+            }
+        }
+        ConflictPolicy::Overwrite => {
+            fs::File::create(partial_path(&out))
+                .unwrap_or_else(|e| panic!("Failed creating {}: {e}", out.display()));
+            Some(out)
+        }
+        ConflictPolicy::Skip if taken => {
+            eprintln!("{} already exists, skipping..", out.display());
+            None
+        }
+        ConflictPolicy::Fail if taken => {
+            panic!(
+                "{} already exists (set `[output] on conflict:` to increment, overwrite or skip)",
+                out.display()
+            )
+        }
+        ConflictPolicy::Skip | ConflictPolicy::Fail => match reserve(&out) {
+            Ok(_) => Some(out),
+            Err(e) if policy == ConflictPolicy::Skip && e.kind() == ErrorKind::AlreadyExists => {
+                eprintln!("{} is already being rendered, skipping..", out.display());
+                None
+            }
+            Err(e) => panic!("Failed creating {}: {e}", out.display()),
+        },
+    }
+}
+
+/// `[output] fruit:` picks the suffix randomly (`random`, default), from the input (`input`) or from a seed number
+fn pick_fruit<'a>(
+    fruits: &[&'a str],
+    recipe: &Recipe,
+    basename: &str,
+    probe: Option<&FfProbe>,
+) -> &'a str {
+    let setting = recipe
+        .get_option("output", "fruit")
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase();
+
+    let index = match setting.as_str() {
+        "" | "random" => {
+            return fruits
+                .choose(&mut rand::rng())
+                .expect("Failed to select a random suffix")
+        }
+        "input" | "hash" => {
+            // hashing the whole video would take a while, its name and size are enough to tell it apart
+            let mut hasher = Sha256::new();
+            hasher.update(basename.as_bytes());
+            if let Some(probe) = probe {
+                hasher.update(probe.format.size.as_bytes());
+            }
+            let digest = hasher.finalize();
+            u64::from_le_bytes(digest[..8].try_into().unwrap())
+        }
+        seed => {
+            let seed = seed.parse::<u64>().unwrap_or_else(|_| {
+                panic!(
+                    "Unknown `[output] fruit:` {seed:?}, expected random, input or a seed number"
+                )
+            });
+            StdRng::seed_from_u64(seed).next_u64()
+        }
+    };
+
+    fruits[(index % fruits.len() as u64) as usize]
+}
+
+/// Deepest folder all inputs are in, what --mirror recreates the structure from
+fn common_root(videos: &[PathBuf]) -> Option<PathBuf> {
+    let mut dirs = videos.iter().filter_map(|video| video.parent());
+    let mut root = dirs.next()?.to_path_buf();
+
+    for dir in dirs {
+        while !dir.starts_with(&root) {
+            root = root.parent()?.to_path_buf();
+        }
+    }
+    Some(root)
+}
+
+pub fn mirroring(args: &Arguments, recipe: &Recipe) -> bool {
+    args.mirror
+        || args.input_root.is_some()
+        || recipe.get_bool_or("output", "mirror folders", false)
+}
+
+/// Generates an output file path, returns None if it's taken and `[output] on conflict:` is skip
 pub fn resolve_outpath(
     args: &mut Arguments,
     recipe: &Recipe,
     in_dir: PathBuf,
     basename: String,
+    probe: Option<&FfProbe>,
     dont_format: bool,
-) -> PathBuf {
+) -> Option<PathBuf> {
+    // nothing gets written, no need to reserve anything
+    let claim = |out: PathBuf| {
+        if !Kind::select(args, recipe).writes_output() {
+            Some(out)
+        } else {
+            claim_outpath(out, ConflictPolicy::from_recipe(recipe))
+        }
+    };
+
     if args.output.is_some() {
-        return PathBuf::from(args.output.as_ref().expect("Failed unwrapping --output"));
+        return claim(PathBuf::from(
+            args.output.as_ref().expect("Failed unwrapping --output"),
+        ));
     }
 
     #[rustfmt::skip]
@@ -93,17 +425,16 @@ pub fn resolve_outpath(
         "Raspberry",  "Starfruit",   "Strawberry",  "Tomato",     "Watermelon",
     ].to_vec();
 
-    let mut format = if dont_format {
+    let format = if dont_format {
         "%FILENAME%-SM".to_string()
     } else {
-        recipe.get("output", "file format").to_uppercase()
-        // .get("output")
-        // .expect("Failed getting [output] from recipe")
-        // .get("file format")
-        // .expect("Failed getting `[output] file format:` from recipe")
-        // .to_uppercase()
+        recipe.get("output", "file format")
     };
 
+    if !format.to_uppercase().contains("%FILENAME%") {
+        panic!("No `%FILENAME%` variable in recipe's `[output] file format:` key");
+    }
+
     let gof:Option<String>=recipe.get_option("miscellaneous", "global output folder"); 
 
     let out_dir = if let Some(ref outdir) = args.outdir {
@@ -120,64 +451,62 @@ pub fn resolve_outpath(
         }
             recipe_path.canonicalize().unwrap_or(recipe_path)
         } else {
-            in_dir
+            in_dir.clone()
         };
 
-    if format.contains("%FRUITS%") || format.contains("%FRUIT") {
-        format = format.replace("%FRUIT%", "%FRUITS%").replace(
-            "%FRUITS%",
-            &format!(
-                " {}",
-                fruits
-                    .choose(&mut rand::rng())
-                    .expect("Failed to select a random suffix")
-            ),
-        );
-    }
-    // create list of vars with section, key, and placeholder name
-    let variables = vec![
-        ("interpolation", "fps", "INTERP_FPS"),
-        ("interpolation", "speed", "SPEED"),
-        ("interpolation", "tuning", "TUNING"),
-        ("interpolation", "algorithm", "ALGORITHM"),
-        ("frame blending", "fps", "OUTPUT_FPS"),
-        ("frame blending", "intensity", "BLUR_AMOUNT"),
-        ("frame blending", "weighting", "WEIGHTING"),
-        ("flowblur", "intensity", "FLOWBLUR_AMOUNT"),
-        ("miscellaneous", "dedup threshold", "DEDUP"),
-        ("pre-interp", "factor", "FACTOR"),
-    ];
-    // loop through each var
-    for (section, key, var) in variables {
-        // check if file format string contains this var's placeholder
-        if format.contains(&format!("%{}%", var)) {
-            // get var value from recipe using section and key
-            let mut value = recipe.get(section, key);
-            // truncate weigting var if too long
-            if key == "weighting" && value.len() > 15 {
-                value = format!("{}..", &value[..15]);
-            }
-            // replace filename forbidden characters with underscores
-            value = value
-                .chars()
-                .map(|c| match c {
-                    '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
-                    _ => c,
-                })
-                .collect();
-            // skip this var if value is empty
-            if value.trim().is_empty() {
-                continue;
-            }
-            // replace placeholder with an actual value
-            format = format.replace(&format!("%{}%", var), &value);
+    // outputs already land next to their inputs when there's no output folder
+    let out_dir = match &args.input_root {
+        Some(root) if mirroring(args, recipe) && out_dir != in_dir => {
+            let in_dir = in_dir.canonicalize().unwrap_or(in_dir);
+            let relative = in_dir.strip_prefix(root).unwrap_or_else(|_| {
+                panic!(
+                    "{} is not inside of the input root {}",
+                    in_dir.display(),
+                    root.display()
+                )
+            });
+            let out_dir = out_dir.join(relative);
+            fs::create_dir_all(&out_dir).unwrap_or_else(|e| {
+                panic!("Failed creating output folder {}: {e}", out_dir.display())
+            });
+            out_dir
         }
-    }
-    if format.contains("%FILENAME") {
-        format = format.replace("%FILENAME%", &basename);
-    } else {
-        panic!("No `%FILENAME%` variable in recipe's `[misc] format:` key");
-    }
+        _ => out_dir,
+    };
+
+    let fruit = pick_fruit(&fruits, recipe, &basename, probe);
+
+    let source = match probe {
+        Some(probe) => template::Source::from_probe(&basename, probe),
+        None => template::Source {
+            basename: basename.clone(),
+            ..Default::default()
+        },
+    };
+    let recipe_name = PathBuf::from(&args.recipe)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let relative = template::render(&format, recipe, &recipe_name, &source, fruit)
+        .unwrap_or_else(|e| panic!("{e}"));
+
+    // `/` in the file format makes subfolders
+    let out_dir = match relative.parent() {
+        Some(subfolder) if !subfolder.as_os_str().is_empty() => {
+            let out_dir = out_dir.join(subfolder);
+            fs::create_dir_all(&out_dir).unwrap_or_else(|e| {
+                panic!("Failed creating output folder {}: {e}", out_dir.display())
+            });
+            out_dir
+        }
+        _ => out_dir,
+    };
+    let format = relative
+        .file_name()
+        .expect("Failed getting output filename from file format")
+        .to_string_lossy()
+        .to_string();
 
     let rc_container = recipe.get("output", "container").trim().to_owned();
     // .expect("Failed getting [output] from recipe")
@@ -186,22 +515,13 @@ pub fn resolve_outpath(
     // .trim();
 
     let container: String = if rc_container.is_empty() {
-        println!("Defaulting output extension to .MP4");
+        eprintln!("Defaulting output extension to .MP4");
         String::from("MP4")
     } else {
         rc_container.replace('.', "")
     };
 
-    let mut out = out_dir.join(format!("{}.{}", &format, &container));
-    let mut round = 2;
-    while out.exists() {
-        out = out_dir
-            .clone()
-            .join(format!("{} ({round}).{}", &format, &container));
-        round += 1;
-    }
-
-    out
+    claim(out_dir.join(format!("{}.{}", &format, &container)))
 }
 
 /// Attempts to resolve and structure input structs from CLI arguments
@@ -280,26 +600,57 @@ pub fn resolve_input(args: &mut Arguments, recipe: &Recipe) -> Vec<Payload> {
                 Some(probe) => probe,
                 None => continue,
             };
-            let timecodes: Vec<Timecodes> = cuts.get(vid).expect("Failed").to_owned();
+            let ctx = crate::timecode::Context::from_probe(&probe);
+            let timecodes: Vec<Timecodes> =
+                match crate::timecode::normalize(cuts.get(vid).expect("Failed"), &ctx) {
+                    Ok(ranges) => crate::timecode::to_timecodes(&ranges),
+                    Err(e) => panic!("Invalid cuts for {}: {e}", vid.display()),
+                };
 
             videos.push((vid.clone(), probe, Some(timecodes)));
         }
     }
 
+    if mirroring(args, recipe) {
+        args.input_root = match &args.input_root {
+            Some(root) => Some(root.canonicalize().unwrap_or_else(|_| {
+                panic!("--input-root {root:?} does not exist or is not a directory")
+            })),
+            None => common_root(
+                &videos
+                    .iter()
+                    .map(|(vid, _, _)| vid.canonicalize().unwrap_or(vid.clone()))
+                    .collect::<Vec<PathBuf>>(),
+            ),
+        };
+        verb!("Mirroring input folders from {:?}", args.input_root);
+    }
+
     for (vid, probe, timecodes) in videos {
+        let out_path = match resolve_outpath(
+            args,
+            recipe,
+            vid.parent().unwrap().to_path_buf(),
+            vid.file_stem()
+                .expect("Failed getting filename base name (stem) when resolving output")
+                .to_str()
+                .expect("Failed converting")
+                .to_string(),
+            Some(&probe),
+            false,
+        ) {
+            Some(out_path) => out_path,
+            None => continue,
+        };
+
+        let reservation = Kind::select(args, recipe)
+            .writes_output()
+            .then(|| Arc::new(Reservation(out_path.clone())));
+
         payloads.push(Payload {
             in_path: vid.clone(),
-            out_path: resolve_outpath(
-                args,
-                recipe,
-                vid.parent().unwrap().to_path_buf(),
-                vid.file_stem()
-                    .expect("Failed getting filename base name (stem) when resolving output")
-                    .to_str()
-                    .expect("Failed converting")
-                    .to_string(),
-                false,
-            ),
+            out_path,
+            reservation,
             basename: vid
                 .file_stem()
                 .expect("Failed getting input filename's base name (stem)")
@@ -0,0 +1,526 @@
+//! Frame blending weights, same math as `scripts/weighting.py` and `blending.py`'s parse_weights2
+//!
+//! Each function returns one weight per blended frame, normalized to sum up to 1
+
+use crate::recipe::Recipe;
+
+/// Scales a list of numbers to sum up to 1, negative weights are shifted up first unless `wizardry`
+fn normalize_with(weights: &[f64], wizardry: bool) -> Vec<f64> {
+    let min = weights.iter().cloned().fold(f64::INFINITY, f64::min);
+
+    let weights: Vec<f64> = if min < 0.0 && !wizardry {
+        weights.iter().map(|w| w + min.abs() + 1.0).collect()
+    } else {
+        weights.to_vec()
+    };
+
+    let total: f64 = weights.iter().sum();
+    weights.iter().map(|w| w / total).collect()
+}
+
+/// Scales a list of numbers to sum up to 1, negative weights are shifted up first
+pub fn normalize(weights: &[f64]) -> Vec<f64> {
+    normalize_with(weights, false)
+}
+
+/// `n` numbers from `start` to `end` (inclusive)
+pub fn scale_range(n: usize, start: f64, end: f64) -> Vec<f64> {
+    if n <= 1 {
+        return vec![start; n];
+    }
+    (0..n)
+        .map(|x| (x as f64 * (end - start) / (n - 1) as f64) + start)
+        .collect()
+}
+
+/// Linear ascending curve
+pub fn ascending(frames: usize) -> Vec<f64> {
+    normalize(&(1..=frames).map(|w| w as f64).collect::<Vec<f64>>())
+}
+
+/// Linear descending curve
+pub fn descending(frames: usize) -> Vec<f64> {
+    normalize(&(1..=frames).rev().map(|w| w as f64).collect::<Vec<f64>>())
+}
+
+/// Flat curve
+pub fn equal(frames: usize) -> Vec<f64> {
+    vec![1.0 / frames as f64; frames]
+}
+
+/// Bell curve, `mean` is where the peak is on the `bound` x-axis, higher `std_dev` is flatter
+pub fn gaussian(frames: usize, mean: f64, std_dev: f64, bound: (f64, f64)) -> Vec<f64> {
+    let weights: Vec<f64> = scale_range(frames, bound.0, bound.1)
+        .iter()
+        .map(|x| (-(x - mean).powi(2) / (2.0 * std_dev.powi(2))).exp())
+        .collect();
+
+    normalize(&weights)
+}
+
+/// Same as [gaussian] but the peak is always at the center
+pub fn gaussian_sym(frames: usize, std_dev: f64, bound: (f64, f64)) -> Vec<f64> {
+    let max_abs = bound.0.abs().max(bound.1.abs());
+    gaussian(frames, 0.0, std_dev, (-max_abs, max_abs))
+}
+
+/// Symmetric pyramid
+pub fn pyramid(frames: usize) -> Vec<f64> {
+    let half = (frames as f64 - 1.0) / 2.0;
+    normalize(
+        &(0..frames)
+            .map(|x| half - (x as f64 - half).abs() + 1.0)
+            .collect::<Vec<f64>>(),
+    )
+}
+
+/// Stretches `weights` to `frames`, `[1, 2]` over 10 frames is `[1, 1, 1, 1, 1, 2, 2, 2, 2, 2]` (normalized)
+fn stretch(frames: usize, weights: &[f64]) -> Vec<f64> {
+    scale_range(frames, 0.0, weights.len() as f64 - 0.1)
+        .iter()
+        .map(|x| weights[*x as usize])
+        .collect()
+}
+
+/// Custom weights (e.g `[1, 2, 3]`), stretched to `frames`
+pub fn divide(frames: usize, weights: &[f64]) -> Vec<f64> {
+    normalize(&stretch(frames, weights))
+}
+
+/// What Vegas does: the first and last frame count half when there's an even amount to blend
+pub fn vegas(input_fps: f64, out_fps: f64, intensity: f64) -> Vec<f64> {
+    let blend_factor = (input_fps / out_fps * intensity) as usize;
+    let frames = blend_factor + (1 - blend_factor % 2);
+
+    let weights: Vec<f64> = if blend_factor.is_multiple_of(2) {
+        (0..frames)
+            .map(|i| if i == 0 || i == frames - 1 { 1.0 } else { 2.0 })
+            .collect()
+    } else {
+        vec![1.0; frames]
+    };
+    normalize(&weights)
+}
+
+/// `(0, 2)` / `[0, 2]`
+fn parse_bound(value: &str) -> Option<(f64, f64)> {
+    let mut values = value
+        .trim_matches(['(', ')', '[', ']'])
+        .split(',')
+        .map(|value| value.trim().parse::<f64>());
+
+    match (values.next(), values.next()) {
+        (Some(Ok(a)), Some(Ok(b))) => Some((a, b)),
+        _ => None,
+    }
+}
+
+/// `[1, 2.5, 3]`
+pub fn parse_list(value: &str) -> Option<Vec<f64>> {
+    let weights = value
+        .strip_prefix('[')?
+        .strip_suffix(']')?
+        .split(',')
+        .map(|value| value.trim().parse::<f64>().ok())
+        .collect::<Option<Vec<f64>>>()?;
+
+    (!weights.is_empty()).then_some(weights)
+}
+
+/// How many frames get blended into one, always odd like blending.py requires
+pub fn frame_count(input_fps: f64, out_fps: f64, intensity: f64) -> usize {
+    // Python's round() goes to the nearest even number on ties
+    let frames = (input_fps.round_ties_even() / out_fps * intensity).round_ties_even() as usize;
+
+    if frames <= 1 {
+        1
+    } else {
+        frames | 1
+    }
+}
+
+/// Weights for `[frame blending] weighting:`, e.g `equal`, `[1, 2, 3]` or `gaussian; mean = 1.5; std_dev = 0.5`
+pub fn parse(
+    weighting: &str,
+    input_fps: f64,
+    out_fps: f64,
+    intensity: f64,
+) -> Result<Vec<f64>, String> {
+    let frames = frame_count(input_fps, out_fps, intensity);
+    if frames == 1 {
+        return Ok(vec![1.0]);
+    }
+
+    let mut parts = weighting
+        .split(';')
+        .map(|part| part.replace(' ', ""))
+        .filter(|part| !part.is_empty());
+    let name = parts.next().ok_or("No weights given")?;
+
+    if name == "vegas" {
+        return Ok(vegas(input_fps.round_ties_even(), out_fps, intensity));
+    }
+
+    let mut wizardry = false;
+    let mut params: Vec<(String, String)> = vec![];
+    for pair in parts {
+        let (param, value) = pair
+            .split_once('=')
+            .ok_or_else(|| format!("Options must be of the form \"name=value\", not \"{pair}\""))?;
+        match param {
+            "frames" => return Err("Cannot set option \"frames\" manually".to_owned()),
+            "wizardry" => wizardry |= crate::YES.contains(&value),
+            _ => params.push((param.to_owned(), value.to_owned())),
+        }
+    }
+
+    let number = |param: &str, value: &str| {
+        value
+            .parse::<f64>()
+            .map_err(|_| format!("Invalid value \"{value}\" for option \"{param}\""))
+    };
+    let bound = |value: &str| {
+        parse_bound(value).ok_or_else(|| format!("Invalid value \"{value}\" for option \"bound\""))
+    };
+    let unknown = |param: &str| Err(format!("Unknown option \"{param}\" for {name} weighting"));
+
+    if name.starts_with('[') && name.ends_with(']') {
+        let weights = parse_list(&name).ok_or_else(|| format!("Invalid weights \"{name}\""))?;
+        if let Some((param, _)) = params.first() {
+            return unknown(param);
+        }
+        return Ok(if wizardry {
+            normalize_with(&stretch(frames, &weights), true)
+        } else {
+            divide(frames, &weights)
+        });
+    }
+
+    match name.as_str() {
+        "equal" | "ascending" | "descending" | "pyramid" => {
+            if let Some((param, _)) = params.first() {
+                return unknown(param);
+            }
+            Ok(match name.as_str() {
+                "equal" => equal(frames),
+                "ascending" => ascending(frames),
+                "descending" => descending(frames),
+                _ => pyramid(frames),
+            })
+        }
+        "gaussian" => {
+            let (mut mean, mut std_dev, mut range) = (2.0, 1.0, (0.0, 2.0));
+            for (param, value) in &params {
+                match param.as_str() {
+                    "mean" => mean = number(param, value)?,
+                    "std_dev" => std_dev = number(param, value)?,
+                    "bound" => range = bound(value)?,
+                    _ => return unknown(param),
+                }
+            }
+            Ok(gaussian(frames, mean, std_dev, range))
+        }
+        "gaussian_sym" => {
+            let (mut std_dev, mut range) = (1.0, (0.0, 2.0));
+            for (param, value) in &params {
+                match param.as_str() {
+                    "std_dev" => std_dev = number(param, value)?,
+                    "bound" => range = bound(value)?,
+                    _ => return unknown(param),
+                }
+            }
+            Ok(gaussian_sym(frames, std_dev, range))
+        }
+        _ => Err(format!("Invalid weighting function: \"{name}\"")),
+    }
+}
+
+/// `[frame blending] weighting:`, equal if it's left empty
+pub fn weighting_of(recipe: &Recipe) -> String {
+    match recipe.get_option("frame blending", "weighting") {
+        Some(weighting) if !crate::NO.contains(&weighting.trim().to_lowercase().as_str()) => {
+            weighting.trim().to_owned()
+        }
+        _ => "equal".to_owned(),
+    }
+}
+
+/// The weights `[frame blending]` blends `input_fps` down with, None if it's disabled or would not bring the fps down
+pub fn from_recipe(recipe: &Recipe, input_fps: f64) -> Option<Result<Vec<f64>, String>> {
+    if !recipe.get_bool_or("frame blending", "enabled", false) {
+        return None;
+    }
+
+    let out_fps = match recipe
+        .get_option("frame blending", "fps")
+        .map(|fps| fps.trim().parse::<f64>())
+    {
+        Some(Ok(fps)) if fps > 0.0 => fps.trunc(),
+        _ => return Some(Err("Invalid `[frame blending] fps:`".to_owned())),
+    };
+    if out_fps >= input_fps + 1.0 {
+        return None;
+    }
+
+    let intensity = match recipe
+        .get_option("frame blending", "intensity")
+        .map(|intensity| intensity.trim().parse::<f64>())
+    {
+        Some(Ok(intensity)) => intensity,
+        _ => return Some(Err("Invalid `[frame blending] intensity:`".to_owned())),
+    };
+
+    Some(parse(&weighting_of(recipe), input_fps, out_fps, intensity))
+}
+
+/// blending.py's format_vec, `[0.20, 0.20, ..., 0.20, 0.20]`
+pub fn format(weights: &[f64]) -> String {
+    let rounded: Vec<String> = weights.iter().map(|w| format!("{w:.2}")).collect();
+
+    if rounded.len() > 4 {
+        format!(
+            "[{}, {}, ..., {}, {}]",
+            rounded[0],
+            rounded[1],
+            rounded[rounded.len() - 2],
+            rounded[rounded.len() - 1]
+        )
+    } else {
+        format!("[{}]", rounded.join(", "))
+    }
+}
+
+/// Writes a custom weighting back, rounded to 2 decimals: `[1, 0.5, 0.25]`
+pub fn format_list(weights: &[f64]) -> String {
+    let weights: Vec<String> = weights
+        .iter()
+        .map(|w| ((w * 100.0).round() / 100.0).to_string())
+        .collect();
+    format!("[{}]", weights.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Expected values are what `blending.parse_weights2` returns for the same input
+    fn golden(weighting: &str, input_fps: f64, out_fps: f64, intensity: f64, expected: &[f64]) {
+        let weights = parse(weighting, input_fps, out_fps, intensity).unwrap();
+
+        assert_eq!(weights.len(), expected.len(), "{weighting}: {weights:?}");
+        for (weight, expected) in weights.iter().zip(expected) {
+            assert!(
+                (weight - expected).abs() < 1e-12,
+                "{weighting}: {weights:?} != {expected:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn linear_curves() {
+        golden("equal", 240.0, 60.0, 1.0, &[0.2, 0.2, 0.2, 0.2, 0.2]);
+        golden(
+            "ascending",
+            240.0,
+            60.0,
+            1.0,
+            &[
+                0.06666666666666667,
+                0.13333333333333333,
+                0.2,
+                0.26666666666666666,
+                0.3333333333333333,
+            ],
+        );
+        golden(
+            "descending",
+            480.0,
+            60.0,
+            1.0,
+            &[
+                0.2,
+                0.17777777777777778,
+                0.15555555555555556,
+                0.13333333333333333,
+                0.1111111111111111,
+                0.08888888888888889,
+                0.06666666666666667,
+                0.044444444444444446,
+                0.022222222222222223,
+            ],
+        );
+        golden(
+            "pyramid",
+            360.0,
+            60.0,
+            1.5,
+            &[0.04, 0.08, 0.12, 0.16, 0.2, 0.16, 0.12, 0.08, 0.04],
+        );
+    }
+
+    #[test]
+    fn gaussian_curves() {
+        golden(
+            "gaussian",
+            480.0,
+            60.0,
+            1.0,
+            &[
+                0.025309623805441654,
+                0.040444663681057993,
+                0.060714631246475505,
+                0.08562135368469381,
+                0.11342986438321595,
+                0.14116574704609347,
+                0.16503947883888612,
+                0.1812604071728965,
+                0.18701423014123902,
+            ],
+        );
+        golden(
+            "gaussian_sym",
+            480.0,
+            60.0,
+            1.0,
+            &[
+                0.027630550638898833,
+                0.06628224528636122,
+                0.12383153680577533,
+                0.1801738229113809,
+                0.20416368871516757,
+                0.1801738229113809,
+                0.12383153680577533,
+                0.06628224528636122,
+                0.027630550638898833,
+            ],
+        );
+        golden(
+            "gaussian; mean = 1.5; std_dev = 0.5",
+            600.0,
+            60.0,
+            1.0,
+            &[
+                0.002001156961842656,
+                0.006133254325952516,
+                0.016018198693697267,
+                0.035649156793547854,
+                0.06760794422230773,
+                0.10925946804255643,
+                0.1504642469579817,
+                0.1765714295007879,
+                0.1765714295007879,
+                0.1504642469579817,
+                0.10925946804255643,
+            ],
+        );
+        golden(
+            "gaussian_sym; std_dev = 2; bound = (0, 3)",
+            240.0,
+            30.0,
+            1.0,
+            &[
+                0.05334957034420673,
+                0.08727407237595917,
+                0.12404146739613511,
+                0.1531707671553173,
+                0.1643282454567633,
+                0.1531707671553173,
+                0.12404146739613511,
+                0.08727407237595917,
+                0.05334957034420673,
+            ],
+        );
+    }
+
+    #[test]
+    fn vegas_halves_the_ends() {
+        golden("vegas", 240.0, 60.0, 1.0, &[0.125, 0.25, 0.25, 0.25, 0.125]);
+        golden("vegas", 240.0, 60.0, 1.2, &[0.125, 0.25, 0.25, 0.25, 0.125]);
+        golden("vegas", 300.0, 60.0, 1.0, &[0.2, 0.2, 0.2, 0.2, 0.2]);
+    }
+
+    #[test]
+    fn custom_lists() {
+        golden(
+            "[1, 2, 3]",
+            480.0,
+            60.0,
+            1.2,
+            &[
+                0.045454545454545456,
+                0.045454545454545456,
+                0.045454545454545456,
+                0.045454545454545456,
+                0.09090909090909091,
+                0.09090909090909091,
+                0.09090909090909091,
+                0.13636363636363635,
+                0.13636363636363635,
+                0.13636363636363635,
+                0.13636363636363635,
+            ],
+        );
+        golden(
+            "[1, -2, 3]",
+            240.0,
+            60.0,
+            1.0,
+            &[
+                0.19047619047619047,
+                0.19047619047619047,
+                0.047619047619047616,
+                0.2857142857142857,
+                0.2857142857142857,
+            ],
+        );
+        golden(
+            "[1, -2, 3]; wizardry = yes",
+            240.0,
+            60.0,
+            1.0,
+            &[
+                0.16666666666666666,
+                0.16666666666666666,
+                -0.3333333333333333,
+                0.5,
+                0.5,
+            ],
+        );
+    }
+
+    #[test]
+    fn frame_counts_round_like_python() {
+        golden("equal", 60.0, 60.0, 1.0, &[1.0]);
+        // 59.94 rounds to 60, 60 / 30 * 1.25 = 2.5 rounds to 2, then 3 to be odd
+        golden(
+            "ascending",
+            60000.0 / 1001.0,
+            30.0,
+            1.25,
+            &[0.16666666666666666, 0.3333333333333333, 0.5],
+        );
+        assert_eq!(frame_count(1000.0, 60.0, 1.0), 17);
+    }
+
+    #[test]
+    fn rejects_bad_weightings() {
+        assert!(parse("sideways", 240.0, 60.0, 1.0).is_err());
+        assert!(parse("equal; frames = 3", 240.0, 60.0, 1.0).is_err());
+        assert!(parse("gaussian; apex = 3", 240.0, 60.0, 1.0).is_err());
+        assert!(parse("[1, two]", 240.0, 60.0, 1.0).is_err());
+    }
+
+    #[test]
+    fn custom_lists_round_trip() {
+        let list = format_list(&[1.0, 0.504, 1.0 / 3.0]);
+        assert_eq!(list, "[1, 0.5, 0.33]");
+        assert_eq!(parse_list(&list), Some(vec![1.0, 0.5, 0.33]));
+        assert_eq!(parse_list("[]"), None);
+    }
+
+    #[test]
+    fn formats_like_blending_py() {
+        assert_eq!(format(&[0.5, 0.5]), "[0.50, 0.50]");
+        assert_eq!(format(&equal(5)), "[0.20, 0.20, ..., 0.20, 0.20]");
+    }
+}